@@ -0,0 +1,51 @@
+// Throughput of the repeated runs of one bench configuration.
+pub struct BenchResult {
+    batch_size: usize,
+    throttle: usize,
+    refresh: String,
+    records_per_sec: Vec<f64>,
+}
+
+impl BenchResult {
+    pub fn new(batch_size: usize, throttle: usize, refresh: String) -> BenchResult {
+        BenchResult {
+            batch_size,
+            throttle,
+            refresh,
+            records_per_sec: Vec::new(),
+        }
+    }
+
+    pub fn add_run(&mut self, records_per_sec: f64) {
+        self.records_per_sec.push(records_per_sec);
+    }
+
+    pub fn runs(&self) -> usize {
+        self.records_per_sec.len()
+    }
+
+    fn mean(&self) -> f64 {
+        self.records_per_sec.iter().sum::<f64>() / self.records_per_sec.len().max(1) as f64
+    }
+
+    // Sample standard deviation, zero for a single run.
+    fn stddev(&self) -> f64 {
+        let runs = self.records_per_sec.len();
+        if runs < 2 {
+            return 0.0;
+        }
+        let mean = self.mean();
+        let variance = self.records_per_sec.iter()
+            .map(|rate| (rate - mean).powi(2))
+            .sum::<f64>() / (runs - 1) as f64;
+        variance.sqrt()
+    }
+}
+
+pub fn print_bench_table(results: &[BenchResult]) {
+    println!("{:>10} {:>8} {:>8} {:>4} {:>14} {:>12}", "batch_size", "throttle", "refresh", "runs", "mean docs/sec", "stddev");
+    for result in results {
+        println!("{:>10} {:>8} {:>8} {:>4} {:>14.1} {:>12.1}",
+            result.batch_size, result.throttle, result.refresh, result.runs(), result.mean(), result.stddev());
+    }
+}
@@ -1,223 +1,517 @@
-use crate::elastic_load::{ElasticLoad, ElasticLoadResults};
+use crate::checkpoint::Checkpoint;
+use crate::concurrency::ConcurrencyLimit;
+use crate::connection::{build_client, ClusterAddress, NodeStatsRecorder, TlsConfig};
+use crate::dead_letter::{DeadLetterFormat, DeadLetterWriter};
+use crate::document_id::IdStrategy;
+use crate::elastic_load::{ConcurrencyStats, ElasticLoad, ElasticLoadResults, LoadFailure, OpType, UpdateBody};
+use crate::index_definition::IndexDefinition;
+use crate::load_error::LoadError;
+use crate::progress::Progress;
+use crate::record_stream::ByteLimit;
+use crate::retry::RetryPolicy;
+use crate::shutdown::Shutdown;
 use serde_json::{Value};
 use serde::{Serialize};
 use elasticsearch::{Elasticsearch};
-use elasticsearch::http::{Url};
-use elasticsearch::http::transport::SingleNodeConnectionPool;
-use elasticsearch::http::transport::TransportBuilder;
+use elasticsearch::http::{StatusCode, Url};
+use elasticsearch::http::request::Body;
 use elasticsearch::http::response::Response;
 use elasticsearch::{BulkOperation, BulkOperations};
 use elasticsearch::BulkParts;
+use elasticsearch::indices::{IndicesCreateParts, IndicesDeleteParts};
 use elasticsearch::auth::Credentials;
-use elasticsearch::cert::CertificateValidation;
 use elasticsearch::params::Refresh;
-use tokio::sync::{Semaphore};
+use tokio::sync::mpsc::Receiver;
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::time::{sleep, sleep_until};
 use futures::stream::{FuturesUnordered, StreamExt};
 
 pub struct BulkElasticLoad {
     client: Elasticsearch,
     index: Option<String>,
     batch_size: usize,
+    batch_bytes: Option<usize>,
     refresh: Refresh,
-    semaphore: Semaphore,
+    throttle: usize,
+    // upper bound when the throttle adapts, None keeps it fixed
+    max_concurrency: Option<usize>,
+    dead_letter: Option<(String, DeadLetterFormat)>,
+    // path, and whether to resume from what it already holds
+    checkpoint: Option<(String, bool)>,
+    // stop at the first request that fails as a whole instead of counting its documents failed
+    abort_on_error: bool,
+    // ingest pipeline the documents go through
+    pipeline: Option<String>,
+    retry_policy: RetryPolicy,
+    index_definition: IndexDefinition,
+    id_strategy: IdStrategy,
+    op_type: OpType,
+    node_stats: NodeStatsRecorder,
+    progress: Arc<Progress>,
+    shutdown: Shutdown,
 }
 
 impl BulkElasticLoad {
     pub fn builder() -> BulkElasticLoadBuilder {
         BulkElasticLoadBuilder::new()
     }
+
+    // Batches received by load are sent as-is, so the producer should cut them to this size.
+    pub fn batch_size(&self) -> usize {
+        self.batch_size
+    }
+
+    // Id of the record at the given (0-based) position of the input; sequence ids start at 1.
+    pub fn document_id<T: Serialize>(&self, position: usize, item: &T) -> Result<String, LoadError> {
+        self.id_strategy.id(position + 1, item)
+    }
+
+    // Whether this load picks up where an earlier one stopped.
+    fn resumes(&self) -> bool {
+        self.checkpoint.as_ref().is_some_and(|(_, resume)| *resume)
+    }
+
+    // Bulk request size batches are also cut at, if any.
+    pub fn byte_limit(&self) -> Option<ByteLimit> {
+        self.batch_bytes.map(|max_bytes| ByteLimit {
+            max_bytes,
+            per_document: self.document_overhead(),
+        })
+    }
+
+    // Bytes each document adds to the request besides its body: the action line, with room
+    // for an id as long as a content hash, and the wrapper of an update.
+    fn document_overhead(&self) -> usize {
+        let action_line = format!(r#"{{"{}":{{"_id":""}}}}"#, self.op_type.action()).len() + ID_ALLOWANCE + 1;
+        match self.op_type {
+            OpType::Update | OpType::Upsert => action_line + r#"{"doc":,"doc_as_upsert":false}"#.len(),
+            _ => action_line,
+        }
+    }
 }
 
 impl BulkElasticLoad {
-    // async fn delete_index(&self) -> Result<(), Box<dyn std::error::Error>> {
-    //     if let Some(index) = &self.index {
-    //         self.client
-    //             .indices()
-    //             .delete(IndicesDeleteParts::Index(&[index.as_str()]))
-    //             .send()
-    //             .await?;
-    //     }
-    //     Ok(())
-    // }
-    //
-    // async fn create_index(&self) -> Result<(), Box<dyn std::error::Error>> {
-    //     if let Some(index) = &self.index {
-    //         self.client
-    //             .indices()
-    //             .create(IndicesCreateParts::Index(&index))
-    //             .body(json!({
-    //                 "settings": {
-    //                     "index": {
-    //                         "number_of_shards": "3",
-    //                         "number_of_replicas": "1",
-    //                         "refresh_interval": "1s",
-    //                     }
-    //                 }
-    //             }))
-    //             .send()
-    //             .await?;
-    //     }
-    //     Ok(())
-    // }
-
-    async fn bulk_load_data<T: Serialize>(&self, items: &[Box<T>], mut start_id: usize) -> Result<ElasticLoadResults, Box<dyn std::error::Error>> {
-        let permit = self.semaphore.acquire().await.unwrap();
-        let mut ops = BulkOperations::new();
-        for item in items {
-            start_id += 1;
-            ops.push(BulkOperation::create(&start_id.to_string(), item))?;
+    async fn delete_index(&self) -> Result<(), LoadError> {
+        if let Some(index) = &self.index {
+            let response = self.client
+                .indices()
+                .delete(IndicesDeleteParts::Index(&[index.as_str()]))
+                .send()
+                .await?;
+            // nothing to delete on the first run
+            if response.status_code() != StatusCode::NOT_FOUND {
+                LoadError::check_response(response).await?;
+            }
+        }
+        Ok(())
+    }
+
+    async fn create_index(&self) -> Result<(), LoadError> {
+        if let Some(index) = &self.index {
+            let response = self.client
+                .indices()
+                .create(IndicesCreateParts::Index(index))
+                .body(self.index_definition.body())
+                .send()
+                .await?;
+            LoadError::check_response(response).await?;
+        }
+        Ok(())
+    }
+
+    // The batch tally along with the positions of any documents that failed, in order.
+    // Rejected items are re-sent on their own; transport errors, 429s and unavailable nodes
+    // re-send the request. A request that can't be sent at all ends with its error.
+    async fn bulk_load_data<T: Serialize>(&self, items: &[T], start_id: usize) -> BatchOutcome {
+        let mut tally = ElasticLoadResults::new();
+        let mut failed_positions = Vec::new();
+        let mut ids = Vec::with_capacity(items.len());
+        let mut pending = Vec::with_capacity(items.len());
+        // a document without an id fails on its own, the rest of the batch is still sent
+        for (position, item) in items.iter().enumerate() {
+            match self.document_id(start_id + position, item) {
+                Ok(id) => {
+                    ids.push(id);
+                    pending.push(position);
+                }
+                Err(error) => {
+                    ids.push(String::new());
+                    tally.num_failed += 1;
+                    tally.failures.push(LoadFailure::from_load_error(String::new(), &error));
+                    failed_positions.push(position);
+                }
+            }
+        }
+        let mut attempt = 1;
+        let error = loop {
+            if pending.is_empty() {
+                break None;
+            }
+            let mut ops = BulkOperations::new();
+            if let Some(error) = pending.iter()
+                .find_map(|&position| self.push_operation(&mut ops, &ids[position], &items[position]).err()) {
+                break Some(LoadError::Serialization(Box::new(error)));
+            }
+
+            let response = match self.send_bulk(ops, pending.len()).await {
+                Ok(response) if !RetryPolicy::is_retryable_status(response.status_code().as_u16()) => response,
+                Ok(_) | Err(_) if self.retry_policy.can_retry(attempt) => {
+                    tally.num_retried += pending.len();
+                    sleep(self.retry_policy.delay(attempt)).await;
+                    attempt += 1;
+                    continue;
+                }
+                Ok(response) => response,
+                Err(e) => break Some(e.into()),
+            };
+
+            let (took, outcomes) = match self.summarize_bulk_load_response(response).await {
+                Ok(summary) => summary,
+                Err(error) => break Some(error),
+            };
+            tally.server_took += took;
+            let mut rejected = Vec::new();
+            for (&position, outcome) in pending.iter().zip(outcomes) {
+                match outcome {
+                    None => tally.num_created += 1,
+                    Some(failure) if failure.is_version_conflict() => tally.num_conflicts += 1,
+                    Some(failure) if failure.status.is_some_and(RetryPolicy::is_retryable_status)
+                        && self.retry_policy.can_retry(attempt) => rejected.push(position),
+                    Some(failure) => {
+                        tally.num_failed += 1;
+                        tally.failures.push(failure);
+                        failed_positions.push(position);
+                    }
+                }
+            }
+            if rejected.is_empty() {
+                break None;
+            }
+            tally.num_retried += rejected.len();
+            sleep(self.retry_policy.delay(attempt)).await;
+            attempt += 1;
+            pending = rejected;
+        };
+        // documents earlier attempts settled keep their outcome, only those still pending fail
+        if let Some(error) = &error {
+            for &position in &pending {
+                tally.num_failed += 1;
+                tally.failures.push(LoadFailure::from_load_error(ids[position].clone(), error));
+                failed_positions.push(position);
+            }
         }
+        tally.num_total = tally.num_created + tally.num_failed + tally.num_conflicts;
+        failed_positions.sort_unstable();
+        BatchOutcome {
+            tally,
+            failed_positions,
+            error,
+        }
+    }
 
+    async fn send_bulk(&self, ops: BulkOperations, documents: usize) -> Result<Response, elasticsearch::Error> {
         let bulk_parts = match &self.index {
             Some(index) => BulkParts::Index(index),
             None => BulkParts::None,
         };
-        let response =
-            self.client
-                .bulk(bulk_parts)
-                .refresh(self.refresh)
-                .body(vec![ops])
-                .send()
-                .await?;
+        let request_bytes = ops.bytes().map_or(0, |body| body.len());
+        let request_start = Instant::now();
+        let mut request = self.client
+            .bulk(bulk_parts)
+            .refresh(self.refresh);
+        if let Some(pipeline) = &self.pipeline {
+            request = request.pipeline(pipeline);
+        }
+        let response = request
+            .body(vec![ops])
+            .send()
+            .await?;
+        self.node_stats.record(response.url(), documents, request_bytes, request_start.elapsed());
+        Ok(response)
+    }
 
-        let tally = self.summarize_bulk_load_response(response).await?;
-        drop(permit);
+    // The batch tally, the source items of the documents that failed, for the dead-letter
+    // file, and the error the request failed with as a whole, if it did.
+    fn settle_batch<T>(outcome: BatchOutcome, items: Vec<T>) -> (ElasticLoadResults, Vec<T>, Option<LoadError>) {
+        let failed_items = if outcome.failed_positions.is_empty() {
+            Vec::new()
+        } else {
+            items.into_iter()
+                .enumerate()
+                .filter(|(position, _)| outcome.failed_positions.binary_search(position).is_ok())
+                .map(|(_, item)| item)
+                .collect()
+        };
+        (outcome.tally, failed_items, outcome.error)
+    }
 
-        Ok(tally)
+    fn push_operation<T: Serialize>(&self, ops: &mut BulkOperations, id: &str, item: &T) -> Result<(), elasticsearch::Error> {
+        match self.op_type {
+            OpType::Create => ops.push(BulkOperation::create(id, item)),
+            OpType::Index => ops.push(BulkOperation::index(item).id(id)),
+            OpType::Update => ops.push(BulkOperation::update(id, UpdateBody { doc: item, doc_as_upsert: false })),
+            OpType::Upsert => ops.push(BulkOperation::update(id, UpdateBody { doc: item, doc_as_upsert: true })),
+            OpType::Delete => ops.push(BulkOperation::<()>::delete(id)),
+        }
     }
 
-    async fn summarize_bulk_load_response(&self, response: Response) -> Result<ElasticLoadResults, Box<dyn std::error::Error>> {
-        let response = response.json::<Value>().await?;
-        let items = response["items"].as_array().unwrap();
-        let mut num_created = 0;
-        let mut num_failed = 0;
+    // The time Elasticsearch reports spending on the request, and one entry per bulk item in
+    // request order: None if it succeeded, otherwise why it failed.
+    async fn summarize_bulk_load_response(&self, response: Response) -> Result<(Duration, Vec<Option<LoadFailure>>), LoadError> {
+        let response = LoadError::check_response(response).await?;
+        let response = response.json::<Value>().await
+            .map_err(|e| LoadError::Serialization(Box::new(e)))?;
+        let took = Duration::from_millis(response["took"].as_u64().unwrap_or_default());
+        let items = response["items"].as_array()
+            .ok_or_else(|| LoadError::BulkItem(String::from("no items in the response")))?;
+        let mut outcomes = Vec::with_capacity(items.len());
         for item in items {
             if let Value::Object(item) = item {
-                if let Value::Object(created_item) = item.get("create").unwrap() {
-                    if created_item.contains_key("errors") {
-                        num_failed += 1;
+                if let Some(Value::Object(op_item)) = item.get(self.op_type.action()) {
+                    if let Some(error) = op_item.get("error") {
+                        let id = op_item.get("_id").and_then(Value::as_str).unwrap_or_default();
+                        let status = op_item.get("status").and_then(Value::as_u64).map(|status| status as u16);
+                        outcomes.push(Some(LoadFailure::from_error(id.to_string(), status, error)));
                     } else {
-                        num_created += 1;
+                        outcomes.push(None);
                     }
                 } else {
-                    return Err("found response besides create and errors".into());
+                    return Err(LoadError::BulkItem(format!("found response besides {} and errors", self.op_type.action())));
                 }
             }
         }
-        Ok(ElasticLoadResults {
-            num_failed,
-            num_created,
-            num_total: num_created + num_failed,
-        })
+        if outcomes.len() != items.len() {
+            return Err(LoadError::BulkItem(String::from("items do not match the request")));
+        }
+        Ok((took, outcomes))
     }
 }
 
+// A bulk request that has come back, along with the batch it carried.
+struct SentBatch<T> {
+    // concurrency generation the request was sent in
+    generation: u64,
+    // source rows of the batch
+    rows: Range<usize>,
+    latency: Duration,
+    outcome: BatchOutcome,
+    items: Vec<T>,
+}
+
+// What came of a bulk request, retries included.
+struct BatchOutcome {
+    tally: ElasticLoadResults,
+    // positions of the documents that failed, in order
+    failed_positions: Vec<usize>,
+    // why the request failed as a whole, after any retries; the documents it was still
+    // sending are among the failed ones
+    error: Option<LoadError>,
+}
+
+// Whether a batch came back with signs of an overloaded cluster: throttled documents or
+// requests that had to be retried or didn't get through at all. Errors that sending less
+// wouldn't fix, like bad credentials, don't count.
+fn is_congested(outcome: &BatchOutcome) -> bool {
+    let failed_request = match &outcome.error {
+        Some(LoadError::Transport(_)) => true,
+        Some(LoadError::Status { status, .. }) => RetryPolicy::is_retryable_status(*status),
+        _ => false,
+    };
+    failed_request
+        || outcome.tally.num_retried > 0
+        || outcome.tally.failures.iter().any(|failure| failure.status == Some(429))
+}
 
 impl ElasticLoad for BulkElasticLoad {
-    // async fn reset_index(&self) -> Result<(), Box<dyn std::error::Error>> {
-    //     self.delete_index().await?;
-    //     self.create_index().await?;
-    //     Ok(())
-    // }
-
-    async fn load<T: Serialize>(&self, items: &[Box<T>]) -> Result<ElasticLoadResults, Box<dyn std::error::Error>> {
-        let items_length = items.len();
-        // for first_idx in (0..items_length).step_by(self.batch_size) {
-        //     let last_idx = {
-        //         let last_idx = first_idx + self.batch_size;
-        //         if last_idx < items_length { last_idx } else { items_length }
-        //     };
-        //     let response =
-        //         self.bulk_load_data(&items[first_idx..last_idx], first_idx).await?;
-        //     tally += self.summarize_bulk_load_response(response).await?;
-        // }
-        let responses = (0..items_length)
-            .step_by(self.batch_size)
-            .map(|first_idx| {
-                let last_idx = {
-                    let last_idx = first_idx + self.batch_size;
-                    if last_idx < items_length { last_idx } else { items_length }
-                };
-                let tally = self.bulk_load_data(&items[first_idx..last_idx], first_idx);
-                tally
-            })
-            .collect::<FuturesUnordered<_>>();
-
-        //     match response {
-        //         Ok(response) => {
-        //             let tally = self.summarize_bulk_load_response(response).await;
-        //             match tally {
-        //                 Ok(tally) => Ok(tally),
-        //                 Err(e) => Err(e),
-        //             }
-        //         }
-        //         Err(e) => Err(e),
-        //     }
-        //     // let tally = ;
-        //     // tally
-        // }
-
-
-        // let mut tallies = responses.next().await
-        //     .into_iter()
-        //     .flatten()
-        //     .map(|response| {
-        //         let tally =
-        //         tally
-        //     })
-        //     .collect::<FuturesUnordered<_>>();
-
-        let tallies = responses
-            .collect::<Vec<Result<ElasticLoadResults, Box<dyn std::error::Error>>>>().await
-            .into_iter()
-            // .into_iter()
-            .flatten()
-            .collect::<Vec<ElasticLoadResults>>();
+    async fn reset_index(&self) -> Result<(), LoadError> {
+        self.delete_index().await?;
+        self.create_index().await?;
+        Ok(())
+    }
 
+    async fn load<T: Serialize>(&self, mut batches: Receiver<Vec<T>>) -> Result<ElasticLoadResults, LoadError> {
         let mut tally_total = ElasticLoadResults::new();
-        for tally in tallies {
-            tally_total += tally;
+        let mut dead_letter = match &self.dead_letter {
+            Some((path, format)) => Some(DeadLetterWriter::create(path, format, self.resumes())?),
+            None => None,
+        };
+        // kept in memory without a checkpoint file, so an interrupted load can still save one
+        let mut checkpoint = match &self.checkpoint {
+            Some((path, resume)) => Checkpoint::open(path, *resume)?,
+            None => Checkpoint::in_memory(),
+        };
+        let mut concurrency = match self.max_concurrency {
+            Some(max_concurrency) => ConcurrencyLimit::adaptive(self.throttle, max_concurrency),
+            None => ConcurrencyLimit::fixed(self.throttle),
+        };
+        let mut in_flight = FuturesUnordered::new();
+        let mut in_flight_rows: usize = 0;
+        let mut next_id: usize = 0;
+        let mut exhausted = false;
+        let mut shutdown = self.shutdown.clone();
+        // set once shutdown is requested, after which only in-flight requests are waited for
+        let mut drain_deadline = None;
+        // Only pull the next batch off the channel once a request slot is free, so the
+        // reader is held back by the channel bound instead of piling batches up here.
+        while !exhausted || !in_flight.is_empty() {
+            tokio::select! {
+                Some(SentBatch { generation, rows, latency, outcome, items }) = in_flight.next(), if !in_flight.is_empty() => {
+                    concurrency.record(generation, latency, is_congested(&outcome));
+                    in_flight_rows -= rows.len();
+                    let (tally, failed_items, error) = Self::settle_batch(outcome, items);
+                    self.progress.batch_done(tally.num_total, tally.num_created, tally.num_failed);
+                    tally_total += tally;
+                    if let Some(dead_letter) = dead_letter.as_mut() {
+                        for item in &failed_items {
+                            dead_letter.write(item)?;
+                        }
+                    }
+                    if let Some(error) = error.filter(|_| self.abort_on_error) {
+                        if let Some(dead_letter) = dead_letter.as_mut() {
+                            dead_letter.flush()?;
+                        }
+                        return Err(error);
+                    }
+                    // failed documents, including those of a request that failed as a whole, are
+                    // in the dead-letter file, so the rows are done with; without one they're left
+                    // out so resuming sends them again
+                    if failed_items.is_empty() || dead_letter.is_some() {
+                        checkpoint.record(rows)?;
+                    }
+                }
+                batch = batches.recv(), if !exhausted && in_flight.len() < concurrency.limit() => {
+                    match batch {
+                        Some(mut batch) => {
+                            let first_id = next_id;
+                            next_id += batch.len();
+                            // rows a resumed checkpoint already has are left out, which can
+                            // leave a batch in several pieces
+                            let pending = checkpoint.pending(first_id..next_id);
+                            tally_total.num_skipped += batch.len() - pending.iter().map(|rows| rows.len()).sum::<usize>();
+                            for rows in pending.into_iter().rev() {
+                                let mut items = batch.split_off(rows.start - first_id);
+                                items.truncate(rows.len());
+                                let generation = concurrency.generation();
+                                in_flight_rows += rows.len();
+                                in_flight.push(async move {
+                                    let sent = Instant::now();
+                                    let outcome = self.bulk_load_data(&items, rows.start).await;
+                                    SentBatch { generation, rows, latency: sent.elapsed(), outcome, items }
+                                });
+                            }
+                        }
+                        None => exhausted = true,
+                    }
+                }
+                _ = shutdown.requested(), if !exhausted => {
+                    exhausted = true;
+                    tally_total.interrupted = true;
+                    drain_deadline = Some(tokio::time::Instant::now() + shutdown.drain_timeout());
+                }
+                _ = sleep_until(drain_deadline.unwrap_or_else(tokio::time::Instant::now)), if drain_deadline.is_some() => {
+                    tally_total.num_abandoned = in_flight_rows;
+                    break;
+                }
+            }
+        }
+        if let Some(dead_letter) = dead_letter.as_mut() {
+            dead_letter.flush()?;
+        }
+        if tally_total.interrupted {
+            let path = match &self.checkpoint {
+                Some((path, _)) => path.clone(),
+                None => {
+                    // one left by an earlier interrupted load is kept, this one goes next to it
+                    let index = self.index.as_deref().unwrap_or("bulk");
+                    let mut path = format!("{index}.checkpoint");
+                    if Path::new(&path).exists() {
+                        let timestamp = SystemTime::now()
+                            .duration_since(UNIX_EPOCH)
+                            .map_or(0, |since_epoch| since_epoch.as_secs());
+                        path = format!("{index}-{timestamp}.checkpoint");
+                    }
+                    checkpoint.save(&path)?;
+                    path
+                }
+            };
+            eprintln!("Interrupted: continue with --checkpoint {path} --resume");
+        }
+        tally_total.node_stats = self.node_stats.take();
+        if concurrency.is_adaptive() {
+            tally_total.concurrency = Some(ConcurrencyStats {
+                converged: concurrency.limit(),
+                peak: concurrency.peak(),
+            });
         }
-
-        //
-        // let tally = ElasticLoadResults {
-        //     num_failed: 0,
-        //     num_created: 0,
-        //     num_total: 0,
-        // };
-        // tally += self.summarize_bulk_load_response(response).await?;
-        // let tally = ElasticLoadResults::new();
-        // while let Some(response) = responses.next().await {
-        // }
         Ok(tally_total)
     }
 }
 
 pub struct BulkElasticLoadBuilder {
-    uri: String,
+    uris: Vec<String>,
+    cloud_id: Option<String>,
     credentials: Option<Credentials>,
     index: Option<String>,
     batch_size: usize,
+    batch_bytes: Option<usize>,
     refresh: Refresh,
     throttle: usize,
+    max_concurrency: Option<usize>,
+    dead_letter: Option<(String, DeadLetterFormat)>,
+    checkpoint: Option<(String, bool)>,
+    abort_on_error: bool,
+    pipeline: Option<String>,
+    retry_policy: RetryPolicy,
+    index_definition: IndexDefinition,
+    id_strategy: IdStrategy,
+    op_type: OpType,
+    tls: TlsConfig,
+    progress: Arc<Progress>,
+    shutdown: Shutdown,
 }
 
 const DEFAULT_BULK_SIZE: usize = 10_000;
+// Id length assumed when sizing requests by bytes; ids can be longer, but rarely are.
+const ID_ALLOWANCE: usize = 64;
 
 impl BulkElasticLoadBuilder {
     pub fn new() -> BulkElasticLoadBuilder {
         BulkElasticLoadBuilder {
-            uri: String::from("https://127.0.0.1:9200/"),
+            uris: vec![String::from("https://127.0.0.1:9200/")],
+            cloud_id: None,
             credentials: None,
             index: None,
             batch_size: DEFAULT_BULK_SIZE,
+            batch_bytes: None,
             refresh: Refresh::False,
             throttle: 1,
+            max_concurrency: None,
+            dead_letter: None,
+            checkpoint: None,
+            abort_on_error: false,
+            pipeline: None,
+            retry_policy: RetryPolicy::none(),
+            index_definition: IndexDefinition::new(),
+            id_strategy: IdStrategy::Sequence,
+            op_type: OpType::Create,
+            tls: TlsConfig::new(),
+            progress: Arc::new(Progress::quiet()),
+            shutdown: Shutdown::never(),
         }
     }
 
-    pub fn with_uri(mut self, uri: String) -> BulkElasticLoadBuilder {
-        self.uri = uri;
+    // Several nodes of the same cluster, used round-robin.
+    pub fn with_uris(mut self, uris: Vec<String>) -> BulkElasticLoadBuilder {
+        self.uris = uris;
+        self
+    }
+
+    // Takes precedence over the uris.
+    pub fn with_cloud_id(mut self, cloud_id: String) -> BulkElasticLoadBuilder {
+        self.cloud_id = Some(cloud_id);
         self
     }
 
@@ -231,11 +525,23 @@ impl BulkElasticLoadBuilder {
         self
     }
 
+    // Elastic recommends bulk requests of a few megabytes rather than a fixed document count.
+    pub fn with_batch_bytes(mut self, batch_bytes: usize) -> BulkElasticLoadBuilder {
+        self.batch_bytes = Some(batch_bytes);
+        self
+    }
+
     pub fn with_throttle(mut self, throttle: usize) -> BulkElasticLoadBuilder {
         self.throttle = throttle;
         self
     }
 
+    // Starts from the throttle and adapts between 1 and `max_concurrency` requests in flight.
+    pub fn with_adaptive_concurrency(mut self, max_concurrency: usize) -> BulkElasticLoadBuilder {
+        self.max_concurrency = Some(max_concurrency);
+        self
+    }
+
     pub fn with_refresh(mut self, refresh: Refresh) -> BulkElasticLoadBuilder {
         self.refresh = refresh;
         self
@@ -246,23 +552,92 @@ impl BulkElasticLoadBuilder {
         self
     }
 
-    pub fn build(self) -> Result<BulkElasticLoad, Box<dyn std::error::Error>> {
-        let url = Url::parse(&self.uri)?;
-        let conn_pool = SingleNodeConnectionPool::new(url);
-        let mut transport_builder = TransportBuilder::new(conn_pool)
-            .disable_proxy()
-            .cert_validation(CertificateValidation::None);
-        if let Some(credentials) = self.credentials {
-            transport_builder = transport_builder.auth(credentials);
-        }
-        let transport = transport_builder.build()?;
-        let client = Elasticsearch::new(transport);
+    pub fn with_dead_letter(mut self, path: String, format: DeadLetterFormat) -> BulkElasticLoadBuilder {
+        self.dead_letter = Some((path, format));
+        self
+    }
+
+    // Records completed rows in `path`; with `resume` the rows it already lists are skipped.
+    pub fn with_checkpoint(mut self, path: String, resume: bool) -> BulkElasticLoadBuilder {
+        self.checkpoint = Some((path, resume));
+        self
+    }
+
+    pub fn with_abort_on_error(mut self, abort_on_error: bool) -> BulkElasticLoadBuilder {
+        self.abort_on_error = abort_on_error;
+        self
+    }
+
+    // Only index and create operations go through the pipeline.
+    pub fn with_pipeline(mut self, pipeline: String) -> BulkElasticLoadBuilder {
+        self.pipeline = Some(pipeline);
+        self
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> BulkElasticLoadBuilder {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    pub fn with_index_definition(mut self, index_definition: IndexDefinition) -> BulkElasticLoadBuilder {
+        self.index_definition = index_definition;
+        self
+    }
+
+    pub fn with_id_strategy(mut self, id_strategy: IdStrategy) -> BulkElasticLoadBuilder {
+        self.id_strategy = id_strategy;
+        self
+    }
+
+    pub fn with_op_type(mut self, op_type: OpType) -> BulkElasticLoadBuilder {
+        self.op_type = op_type;
+        self
+    }
+
+    pub fn with_tls(mut self, tls: TlsConfig) -> BulkElasticLoadBuilder {
+        self.tls = tls;
+        self
+    }
+
+    pub fn with_progress(mut self, progress: Arc<Progress>) -> BulkElasticLoadBuilder {
+        self.progress = progress;
+        self
+    }
+
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> BulkElasticLoadBuilder {
+        self.shutdown = shutdown;
+        self
+    }
+
+    pub fn build(self) -> Result<BulkElasticLoad, LoadError> {
+        let address = match self.cloud_id {
+            Some(cloud_id) => ClusterAddress::CloudId(cloud_id),
+            None => ClusterAddress::Urls(self.uris.iter()
+                .map(|uri| Url::parse(uri))
+                .collect::<Result<Vec<Url>, _>>()
+                .map_err(|e| LoadError::Config(format!("invalid cluster url: {e}")))?),
+        };
+        let client = build_client(address, self.credentials, &self.tls)
+            .map_err(|e| LoadError::Config(e.to_string()))?;
         Ok(BulkElasticLoad {
             client,
             index: self.index,
             batch_size: self.batch_size,
+            batch_bytes: self.batch_bytes,
             refresh: self.refresh,
-            semaphore: Semaphore::new(self.throttle),
+            throttle: self.throttle.max(1),
+            max_concurrency: self.max_concurrency,
+            dead_letter: self.dead_letter,
+            checkpoint: self.checkpoint,
+            abort_on_error: self.abort_on_error,
+            pipeline: self.pipeline,
+            retry_policy: self.retry_policy,
+            index_definition: self.index_definition,
+            id_strategy: self.id_strategy,
+            op_type: self.op_type,
+            node_stats: NodeStatsRecorder::new(),
+            progress: self.progress,
+            shutdown: self.shutdown,
         })
     }
 }
@@ -0,0 +1,176 @@
+use crate::load_error::LoadError;
+use serde_json::{json, Value};
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::ops::Range;
+
+// Source row ranges (0-based, end exclusive) whose batches Elasticsearch acknowledged, one
+// JSON line per batch. Lines are appended and flushed as batches complete, so the file stays
+// usable however the load ends.
+pub struct Checkpoint {
+    // None when no checkpoint file was asked for; the ranges are then kept in `recorded`
+    // until they're saved, if ever
+    file: Option<File>,
+    // what earlier runs completed, sorted and merged
+    completed: Vec<Range<usize>>,
+    recorded: Vec<Range<usize>>,
+}
+
+impl Checkpoint {
+    // Starts a new checkpoint file, or with `resume` picks up the ranges already in it.
+    pub fn open(path: &str, resume: bool) -> Result<Checkpoint, LoadError> {
+        let mut completed = Vec::new();
+        if resume {
+            let file = File::open(path)
+                .map_err(|e| LoadError::Checkpoint(format!("can't resume from checkpoint {path}: {e}")))?;
+            for (line_idx, line) in BufReader::new(file).lines().enumerate() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                let range = serde_json::from_str::<Value>(&line)
+                    .ok()
+                    .and_then(|range| Some(range["start"].as_u64()? as usize..range["end"].as_u64()? as usize))
+                    .ok_or_else(|| LoadError::Checkpoint(format!("{path} line {}: not a checkpoint range", line_idx + 1)))?;
+                completed.push(range);
+            }
+        }
+        completed.sort_by_key(|range| range.start);
+        let mut merged: Vec<Range<usize>> = Vec::with_capacity(completed.len());
+        for range in completed {
+            match merged.last_mut() {
+                Some(last) if range.start <= last.end => last.end = last.end.max(range.end),
+                _ => merged.push(range),
+            }
+        }
+
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(resume)
+            .truncate(!resume)
+            .open(path)?;
+        Ok(Checkpoint {
+            file: Some(file),
+            completed: merged,
+            recorded: Vec::new(),
+        })
+    }
+
+    pub fn in_memory() -> Checkpoint {
+        Checkpoint {
+            file: None,
+            completed: Vec::new(),
+            recorded: Vec::new(),
+        }
+    }
+
+    pub fn record(&mut self, range: Range<usize>) -> Result<(), LoadError> {
+        match self.file.as_mut() {
+            Some(file) => {
+                write_range(file, &range)?;
+                file.flush()?;
+            }
+            None => self.recorded.push(range),
+        }
+        Ok(())
+    }
+
+    // Writes what an in-memory checkpoint recorded to a new file, so a load that didn't ask
+    // for a checkpoint can still be resumed. An existing file is never overwritten.
+    pub fn save(&self, path: &str) -> Result<(), LoadError> {
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(path)
+            .map_err(|e| LoadError::Checkpoint(format!("could not save the checkpoint to {path}: {e}")))?;
+        for range in &self.recorded {
+            write_range(&mut file, range)?;
+        }
+        file.flush()?;
+        Ok(())
+    }
+
+    // The parts of `range` earlier runs didn't complete.
+    pub fn pending(&self, range: Range<usize>) -> Vec<Range<usize>> {
+        let mut pending = Vec::new();
+        let mut start = range.start;
+        for completed in &self.completed {
+            if completed.end <= start {
+                continue;
+            }
+            if completed.start >= range.end {
+                break;
+            }
+            if completed.start > start {
+                pending.push(start..completed.start);
+            }
+            start = start.max(completed.end);
+        }
+        if start < range.end {
+            pending.push(start..range.end);
+        }
+        pending
+    }
+}
+
+fn write_range(file: &mut File, range: &Range<usize>) -> std::io::Result<()> {
+    writeln!(file, "{}", json!({ "start": range.start, "end": range.end }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_file::TempFile;
+
+    fn checkpoint_file(name: &str, lines: &[&str]) -> TempFile {
+        TempFile::new(&format!("{name}.checkpoint"), &lines.join("\n"))
+    }
+
+    fn resume(file: &TempFile) -> Result<Checkpoint, LoadError> {
+        Checkpoint::open(file.path(), true)
+    }
+
+    fn resumed(name: &str, ranges: &[(usize, usize)]) -> (TempFile, Checkpoint) {
+        let lines = ranges.iter()
+            .map(|(start, end)| json!({ "start": start, "end": end }).to_string())
+            .collect::<Vec<String>>();
+        let file = checkpoint_file(name, &lines.iter().map(String::as_str).collect::<Vec<&str>>());
+        let checkpoint = resume(&file).unwrap();
+        (file, checkpoint)
+    }
+
+    #[test]
+    fn pending_is_everything_without_earlier_runs() {
+        assert_eq!(Checkpoint::in_memory().pending(0..10), vec![0..10]);
+    }
+
+    #[test]
+    fn pending_splits_around_completed_ranges() {
+        let (_file, checkpoint) = resumed("split", &[(10, 20), (30, 40)]);
+        assert_eq!(checkpoint.pending(0..50), vec![0..10, 20..30, 40..50]);
+        assert_eq!(checkpoint.pending(15..35), vec![20..30]);
+        assert_eq!(checkpoint.pending(10..20), Vec::<Range<usize>>::new());
+        assert_eq!(checkpoint.pending(12..18), Vec::<Range<usize>>::new());
+        assert_eq!(checkpoint.pending(20..30), vec![20..30]);
+        assert_eq!(checkpoint.pending(45..50), vec![45..50]);
+    }
+
+    #[test]
+    fn pending_merges_overlapping_and_adjacent_ranges() {
+        // out of order, overlapping and touching, as concurrent batches complete
+        let (_file, checkpoint) = resumed("overlap", &[(30, 35), (10, 20), (15, 30), (40, 50), (35, 38)]);
+        assert_eq!(checkpoint.completed, vec![10..38, 40..50]);
+        assert_eq!(checkpoint.pending(0..60), vec![0..10, 38..40, 50..60]);
+        assert_eq!(checkpoint.pending(12..45), vec![38..40]);
+    }
+
+    #[test]
+    fn open_skips_blank_lines_and_rejects_others() {
+        let file = checkpoint_file("blank", &[r#"{"start":0,"end":5}"#, "", r#"{"start":5,"end":10}"#]);
+        assert_eq!(resume(&file).unwrap().pending(0..12), vec![10..12]);
+
+        let file = checkpoint_file("invalid", &[r#"{"start":0,"end":5}"#, r#"{"start":5}"#]);
+        assert!(matches!(resume(&file), Err(LoadError::Checkpoint(message)) if message.contains("line 2")));
+    }
+}
@@ -0,0 +1,171 @@
+use std::time::Duration;
+
+// A latency this many times the best seen so far counts as the cluster struggling.
+const LATENCY_SPIKE_FACTOR: f64 = 2.0;
+// Weight of each new sample in the smoothed latency.
+const LATENCY_SMOOTHING: f64 = 0.2;
+
+// How many requests may be in flight at once. Fixed keeps the configured throttle; adaptive
+// grows it by one for every round of healthy requests and halves it whenever requests come
+// back rejected or noticeably slower (AIMD), staying between 1 and `max`.
+pub struct ConcurrencyLimit {
+    limit: usize,
+    max: usize,
+    adaptive: bool,
+    // completions at the current limit since it last grew
+    successes: usize,
+    // bumped on every decrease, so requests sent before it don't halve the limit again
+    generation: u64,
+    smoothed_latency: Option<Duration>,
+    best_latency: Option<Duration>,
+    peak: usize,
+}
+
+impl ConcurrencyLimit {
+    pub fn fixed(limit: usize) -> ConcurrencyLimit {
+        let limit = limit.max(1);
+        ConcurrencyLimit {
+            limit,
+            max: limit,
+            adaptive: false,
+            successes: 0,
+            generation: 0,
+            smoothed_latency: None,
+            best_latency: None,
+            peak: limit,
+        }
+    }
+
+    pub fn adaptive(initial: usize, max: usize) -> ConcurrencyLimit {
+        let max = max.max(1);
+        let limit = initial.clamp(1, max);
+        ConcurrencyLimit {
+            max,
+            adaptive: true,
+            peak: limit,
+            ..ConcurrencyLimit::fixed(limit)
+        }
+    }
+
+    pub fn limit(&self) -> usize {
+        self.limit
+    }
+
+    pub fn peak(&self) -> usize {
+        self.peak
+    }
+
+    pub fn is_adaptive(&self) -> bool {
+        self.adaptive
+    }
+
+    // Tag for a request about to be sent, handed back to `record` once it completes.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    // `congested` means the request was rejected, throttled or failed to get through.
+    pub fn record(&mut self, generation: u64, latency: Duration, congested: bool) {
+        if !self.adaptive {
+            return;
+        }
+        let smoothed = match self.smoothed_latency {
+            Some(smoothed) => smoothed.mul_f64(1.0 - LATENCY_SMOOTHING) + latency.mul_f64(LATENCY_SMOOTHING),
+            None => latency,
+        };
+        self.smoothed_latency = Some(smoothed);
+        let best = self.best_latency.map_or(smoothed, |best| best.min(smoothed));
+        self.best_latency = Some(best);
+
+        let spiking = smoothed > best.mul_f64(LATENCY_SPIKE_FACTOR);
+        if congested || spiking {
+            if generation == self.generation {
+                self.limit = (self.limit / 2).max(1);
+                self.successes = 0;
+                self.generation += 1;
+            }
+            return;
+        }
+
+        self.successes += 1;
+        if self.successes >= self.limit && self.limit < self.max {
+            self.limit += 1;
+            self.successes = 0;
+            self.peak = self.peak.max(self.limit);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FAST: Duration = Duration::from_millis(10);
+
+    // Completes one healthy request sent at the current limit.
+    fn succeed(concurrency: &mut ConcurrencyLimit) {
+        let generation = concurrency.generation();
+        concurrency.record(generation, FAST, false);
+    }
+
+    #[test]
+    fn fixed_limit_never_changes() {
+        let mut concurrency = ConcurrencyLimit::fixed(4);
+        concurrency.record(concurrency.generation(), FAST, true);
+        for _ in 0..100 {
+            succeed(&mut concurrency);
+        }
+        assert_eq!(concurrency.limit(), 4);
+        assert_eq!(ConcurrencyLimit::fixed(0).limit(), 1);
+    }
+
+    #[test]
+    fn congestion_halves_the_limit_once_per_generation() {
+        let mut concurrency = ConcurrencyLimit::adaptive(8, 32);
+        let sent_before = concurrency.generation();
+        concurrency.record(sent_before, FAST, true);
+        assert_eq!(concurrency.limit(), 4);
+        // other requests sent at the old limit coming back congested too don't count again
+        concurrency.record(sent_before, FAST, true);
+        concurrency.record(sent_before, FAST, true);
+        assert_eq!(concurrency.limit(), 4);
+
+        concurrency.record(concurrency.generation(), FAST, true);
+        assert_eq!(concurrency.limit(), 2);
+    }
+
+    #[test]
+    fn latency_spike_halves_the_limit() {
+        let mut concurrency = ConcurrencyLimit::adaptive(8, 32);
+        for _ in 0..3 {
+            succeed(&mut concurrency);
+        }
+        concurrency.record(concurrency.generation(), FAST * 10, false);
+        assert_eq!(concurrency.limit(), 4);
+    }
+
+    #[test]
+    fn grows_by_one_per_round_of_successes_up_to_max() {
+        let mut concurrency = ConcurrencyLimit::adaptive(2, 4);
+        succeed(&mut concurrency);
+        assert_eq!(concurrency.limit(), 2);
+        succeed(&mut concurrency);
+        assert_eq!(concurrency.limit(), 3);
+        for _ in 0..100 {
+            succeed(&mut concurrency);
+        }
+        assert_eq!(concurrency.limit(), 4);
+        assert_eq!(concurrency.peak(), 4);
+    }
+
+    #[test]
+    fn limit_never_drops_below_one() {
+        let mut concurrency = ConcurrencyLimit::adaptive(3, 8);
+        for _ in 0..10 {
+            concurrency.record(concurrency.generation(), FAST, true);
+        }
+        assert_eq!(concurrency.limit(), 1);
+        assert_eq!(concurrency.peak(), 3);
+        assert_eq!(ConcurrencyLimit::adaptive(0, 0).limit(), 1);
+    }
+}
@@ -0,0 +1,423 @@
+use crate::elastic_load::NodeStats;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use elasticsearch::Elasticsearch;
+use elasticsearch::auth::Credentials;
+use elasticsearch::cluster::ClusterHealthParts;
+use elasticsearch::cert::{Certificate, CertificateValidation};
+use elasticsearch::http::{StatusCode, Url};
+use elasticsearch::http::transport::{CloudConnectionPool, CloudId, Connection, ConnectionPool, SingleNodeConnectionPool, TransportBuilder};
+use elasticsearch::nodes::NodesInfoParts;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+use tokio::task::spawn_blocking;
+use tokio::time::sleep;
+
+const FINGERPRINT_CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+// Longest proxy response to a CONNECT that's read before giving up on it.
+const MAX_PROXY_RESPONSE_BYTES: usize = 8192;
+const CLUSTER_HEALTH_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum TlsValidation {
+    // Accept any certificate; the original behaviour, fine against a throwaway local cluster.
+    None,
+    // Signed by the CA, but the hostname isn't checked.
+    Certificate,
+    // Signed by the CA (or a system root without one) and issued for the hostname.
+    Full,
+}
+
+#[derive(Clone)]
+pub enum ProxyConfig {
+    Disabled,
+    // Whatever HTTP(S)_PROXY and friends say.
+    System,
+    Url(Url),
+}
+
+// How the loaders connect: certificate validation and proxies.
+#[derive(Clone)]
+pub struct TlsConfig {
+    validation: TlsValidation,
+    ca_cert: Option<String>,
+    proxy: ProxyConfig,
+}
+
+impl TlsConfig {
+    pub fn new() -> TlsConfig {
+        TlsConfig {
+            validation: TlsValidation::None,
+            ca_cert: None,
+            proxy: ProxyConfig::Disabled,
+        }
+    }
+
+    pub fn with_validation(mut self, validation: TlsValidation) -> TlsConfig {
+        self.validation = validation;
+        self
+    }
+
+    // PEM file holding the CA, ex the certs/ca/ca.crt generated by docker-compose.
+    pub fn with_ca_cert(mut self, ca_cert: String) -> TlsConfig {
+        self.ca_cert = Some(ca_cert);
+        self
+    }
+
+    pub fn with_proxy(mut self, proxy: ProxyConfig) -> TlsConfig {
+        self.proxy = proxy;
+        self
+    }
+
+    fn configure(&self, mut transport_builder: TransportBuilder) -> Result<TransportBuilder, Box<dyn std::error::Error>> {
+        transport_builder = match &self.proxy {
+            ProxyConfig::Disabled => transport_builder.disable_proxy(),
+            ProxyConfig::System => transport_builder,
+            ProxyConfig::Url(proxy) => {
+                let username = Some(proxy.username()).filter(|username| !username.is_empty());
+                transport_builder.proxy(proxy.clone(), username, proxy.password())
+            }
+        };
+
+        let validation = match (self.validation, &self.ca_cert) {
+            (TlsValidation::None, _) => CertificateValidation::None,
+            (TlsValidation::Certificate, Some(ca_cert)) => CertificateValidation::Certificate(read_certificate(ca_cert)?),
+            (TlsValidation::Certificate, None) => return Err("certificate validation needs a CA certificate".into()),
+            (TlsValidation::Full, Some(ca_cert)) => CertificateValidation::Full(read_certificate(ca_cert)?),
+            (TlsValidation::Full, None) => CertificateValidation::Default,
+        };
+        Ok(transport_builder.cert_validation(validation))
+    }
+
+    // The proxy requests to `url` go through, if any.
+    fn proxy_for(&self, url: &Url) -> Result<Option<Url>, Box<dyn std::error::Error>> {
+        match &self.proxy {
+            ProxyConfig::Disabled => Ok(None),
+            ProxyConfig::Url(proxy) => Ok(Some(proxy.clone())),
+            ProxyConfig::System => system_proxy(url),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum ClusterAddress {
+    // Requests are spread over the nodes round-robin.
+    Urls(Vec<Url>),
+    // Elastic Cloud deployment id, as shown in the cloud console.
+    CloudId(String),
+}
+
+impl ClusterAddress {
+    fn urls(&self) -> Result<Vec<Url>, Box<dyn std::error::Error>> {
+        Ok(match self {
+            ClusterAddress::Urls(urls) => urls.clone(),
+            ClusterAddress::CloudId(cloud_id) => vec![CloudId::parse(cloud_id)?.url],
+        })
+    }
+}
+
+pub fn build_client(address: ClusterAddress, credentials: Option<Credentials>, tls: &TlsConfig) -> Result<Elasticsearch, Box<dyn std::error::Error>> {
+    let transport_builder = match address {
+        ClusterAddress::Urls(urls) if urls.len() == 1 => TransportBuilder::new(SingleNodeConnectionPool::new(urls[0].clone())),
+        ClusterAddress::Urls(urls) => TransportBuilder::new(RoundRobinConnectionPool::new(&urls)?),
+        ClusterAddress::CloudId(cloud_id) => TransportBuilder::new(CloudConnectionPool::new(&cloud_id)?),
+    };
+    let mut transport_builder = tls.configure(transport_builder)?;
+    if let Some(credentials) = credentials {
+        transport_builder = transport_builder.auth(credentials);
+    }
+    let transport = transport_builder.build()?;
+    Ok(Elasticsearch::new(transport))
+}
+
+// Health the cluster has to reach before loading starts.
+#[derive(Clone, Copy, PartialEq, PartialOrd)]
+pub enum ClusterStatus {
+    Red,
+    Yellow,
+    Green,
+}
+
+impl ClusterStatus {
+    fn parse(status: &str) -> Option<ClusterStatus> {
+        match status {
+            "red" => Some(ClusterStatus::Red),
+            "yellow" => Some(ClusterStatus::Yellow),
+            "green" => Some(ClusterStatus::Green),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            ClusterStatus::Red => "red",
+            ClusterStatus::Yellow => "yellow",
+            ClusterStatus::Green => "green",
+        }
+    }
+}
+
+// Polls cluster health until it's at least `status`. Nodes that aren't up yet are waited
+// for, but rejected credentials, or credentials not allowed to read cluster health, fail
+// straight away.
+pub async fn wait_for_cluster(address: ClusterAddress, credentials: Option<Credentials>, tls: &TlsConfig, status: ClusterStatus, timeout: Duration) -> Result<(), Box<dyn std::error::Error>> {
+    let deadline = Instant::now() + timeout;
+    let client = build_client(address, credentials, tls)?;
+    loop {
+        // a node that accepts connections but never answers mustn't outlast the deadline
+        let remaining = deadline.saturating_duration_since(Instant::now());
+        let response = client.cluster()
+            .health(ClusterHealthParts::None)
+            .request_timeout(remaining)
+            .send()
+            .await;
+        let last_error = match response {
+            Ok(response) if response.status_code() == StatusCode::UNAUTHORIZED => {
+                return Err(format!("cluster rejected the credentials with {}", response.status_code()).into());
+            }
+            // cluster health needs the monitor privilege, which credentials that can only
+            // index may well lack
+            Ok(response) if response.status_code() == StatusCode::FORBIDDEN => {
+                return Err("the credentials aren't allowed to read cluster health (it needs the monitor privilege); \
+                    give --wait-for-cluster none to load without waiting for the cluster".into());
+            }
+            Ok(response) => {
+                let health = response.json::<Value>().await?;
+                match health["status"].as_str().and_then(ClusterStatus::parse) {
+                    Some(current) if current >= status => return Ok(()),
+                    Some(current) => format!("cluster health is {}", current.name()),
+                    None => format!("unexpected cluster health response {health}"),
+                }
+            }
+            Err(e) => e.to_string(),
+        };
+        if Instant::now() + CLUSTER_HEALTH_POLL_INTERVAL > deadline {
+            return Err(format!("cluster not {} after {timeout:?}: {last_error}", status.name()).into());
+        }
+        sleep(CLUSTER_HEALTH_POLL_INTERVAL).await;
+    }
+}
+
+// Checks, once before loading, that every node in `address` presents a certificate with the
+// SHA-256 fingerprint `expected` (hex, colons optional). The client can't pin certificates
+// itself, so this is a pre-check only: connections made afterwards don't validate the
+// certificate. Nodes that can't be reached yet are retried until `timeout`, a certificate
+// that doesn't match fails straight away.
+pub async fn check_cert_fingerprint(address: &ClusterAddress, tls: &TlsConfig, expected: &str, timeout: Duration) -> Result<(), Box<dyn std::error::Error>> {
+    let expected = expected.replace(':', "").to_lowercase();
+    let deadline = Instant::now() + timeout;
+    for url in address.urls()? {
+        if url.scheme() != "https" {
+            return Err(format!("{url} isn't https, so it has no certificate fingerprint to check").into());
+        }
+        let proxy = tls.proxy_for(&url)?;
+        loop {
+            // the check is plain blocking I/O, kept off the runtime's threads
+            let (node, through) = (url.clone(), proxy.clone());
+            let fingerprint = spawn_blocking(move || certificate_fingerprint(&node, through.as_ref()).map_err(|e| e.to_string())).await?;
+            match fingerprint {
+                Ok(actual) if actual == expected => break,
+                Ok(actual) => return Err(format!("certificate fingerprint of {url} is {actual}, expected {expected}").into()),
+                Err(e) if Instant::now() + CLUSTER_HEALTH_POLL_INTERVAL > deadline => {
+                    return Err(format!("could not check the certificate of {url} after {timeout:?}: {e}").into());
+                }
+                Err(_) => sleep(CLUSTER_HEALTH_POLL_INTERVAL).await,
+            }
+        }
+    }
+    Ok(())
+}
+
+// Asks the seed nodes for every HTTP-enabled node in the cluster. Nodes are addressed by
+// their publish address, which has to be reachable from here; inside docker-compose that's
+// the container network, not the ports published on the host.
+pub async fn sniff_nodes(seeds: &[Url], credentials: Option<Credentials>, tls: &TlsConfig) -> Result<Vec<Url>, Box<dyn std::error::Error>> {
+    let seed = seeds.first().ok_or("no cluster url to sniff from")?;
+    let client = build_client(ClusterAddress::Urls(seeds.to_vec()), credentials, tls)?;
+    let response = client
+        .nodes()
+        .info(NodesInfoParts::Metric(&["http"]))
+        .send()
+        .await?
+        .error_for_status_code()?
+        .json::<Value>()
+        .await?;
+    let mut urls = Vec::new();
+    if let Some(nodes) = response["nodes"].as_object() {
+        for node in nodes.values() {
+            // "ip:port", or "hostname/ip:port" when a publish host is configured
+            if let Some(address) = node["http"]["publish_address"].as_str() {
+                let address = address.rsplit('/').next().unwrap_or(address);
+                urls.push(Url::parse(&format!("{}://{address}/", seed.scheme()))?);
+            }
+        }
+    }
+    if urls.is_empty() {
+        return Err("sniffing found no nodes with HTTP enabled".into());
+    }
+    urls.sort();
+    Ok(urls)
+}
+
+// Hands out the nodes in turn. The client only ever asks its pool for a connection, so this
+// is enough to spread requests evenly over the cluster.
+#[derive(Debug, Clone)]
+struct RoundRobinConnectionPool {
+    connections: Vec<Connection>,
+    next: Arc<AtomicUsize>,
+}
+
+impl RoundRobinConnectionPool {
+    fn new(urls: &[Url]) -> Result<RoundRobinConnectionPool, Box<dyn std::error::Error>> {
+        if urls.is_empty() {
+            return Err("at least one cluster url is required".into());
+        }
+        Ok(RoundRobinConnectionPool {
+            connections: urls.iter().cloned().map(Connection::new).collect(),
+            next: Arc::new(AtomicUsize::new(0)),
+        })
+    }
+}
+
+impl ConnectionPool for RoundRobinConnectionPool {
+    fn next(&self) -> &Connection {
+        let idx = self.next.fetch_add(1, Ordering::Relaxed) % self.connections.len();
+        &self.connections[idx]
+    }
+}
+
+// Requests and documents handled by each node, keyed by "host:port".
+pub struct NodeStatsRecorder {
+    stats: Mutex<BTreeMap<String, NodeStats>>,
+}
+
+impl NodeStatsRecorder {
+    pub fn new() -> NodeStatsRecorder {
+        NodeStatsRecorder {
+            stats: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn record(&self, url: &Url, documents: usize, bytes: usize, elapsed: Duration) {
+        let node = format!("{}:{}", url.host_str().unwrap_or_default(), url.port_or_known_default().unwrap_or_default());
+        let mut stats = self.stats.lock().unwrap();
+        let node_stats = stats.entry(node).or_default();
+        node_stats.requests += 1;
+        node_stats.documents += documents;
+        node_stats.bytes += bytes;
+        node_stats.elapsed += elapsed;
+        node_stats.latencies.record(elapsed);
+    }
+
+    // Hands over what was recorded so far and starts again from zero.
+    pub fn take(&self) -> BTreeMap<String, NodeStats> {
+        std::mem::take(&mut *self.stats.lock().unwrap())
+    }
+}
+
+fn read_certificate(path: &str) -> Result<Certificate, Box<dyn std::error::Error>> {
+    Ok(Certificate::from_pem(&std::fs::read(path)?)?)
+}
+
+// Connects the way the client would, through the proxy if there's one, and returns the
+// SHA-256 of the certificate the node presents, as lowercase hex.
+fn certificate_fingerprint(url: &Url, proxy: Option<&Url>) -> Result<String, Box<dyn std::error::Error>> {
+    let host = url.host_str().ok_or("cluster url has no host")?;
+    let port = url.port_or_known_default().ok_or("cluster url has no port")?;
+    let stream = match proxy {
+        Some(proxy) => connect_through_proxy(proxy, host, port)?,
+        None => connect(host, port)?,
+    };
+    let connector = native_tls::TlsConnector::builder()
+        .danger_accept_invalid_certs(true)
+        .danger_accept_invalid_hostnames(true)
+        .build()?;
+    let stream = connector.connect(host, stream)
+        .map_err(|e| format!("TLS handshake with {host}:{port} failed: {e}"))?;
+    let certificate = stream.peer_certificate()?
+        .ok_or_else(|| format!("{host}:{port} did not present a certificate"))?;
+
+    Ok(Sha256::digest(certificate.to_der()?)
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect())
+}
+
+fn connect(host: &str, port: u16) -> Result<TcpStream, Box<dyn std::error::Error>> {
+    let addr = (host, port).to_socket_addrs()?
+        .next()
+        .ok_or_else(|| format!("could not resolve {host}"))?;
+    let stream = TcpStream::connect_timeout(&addr, FINGERPRINT_CONNECT_TIMEOUT)?;
+    // a node or proxy that accepts the connection and then says nothing mustn't hang the check
+    stream.set_read_timeout(Some(FINGERPRINT_CONNECT_TIMEOUT))?;
+    stream.set_write_timeout(Some(FINGERPRINT_CONNECT_TIMEOUT))?;
+    Ok(stream)
+}
+
+// Opens a tunnel to host:port with an HTTP CONNECT, the TLS handshake then goes through it.
+fn connect_through_proxy(proxy: &Url, host: &str, port: u16) -> Result<TcpStream, Box<dyn std::error::Error>> {
+    if proxy.scheme() != "http" {
+        return Err(format!("only http proxies can be used to check the certificate fingerprint, not {proxy}").into());
+    }
+    let proxy_host = proxy.host_str().ok_or("proxy url has no host")?;
+    let proxy_port = proxy.port_or_known_default().ok_or("proxy url has no port")?;
+    let mut stream = connect(proxy_host, proxy_port)?;
+
+    let mut request = format!("CONNECT {host}:{port} HTTP/1.1\r\nHost: {host}:{port}\r\n");
+    if !proxy.username().is_empty() {
+        let credentials = format!("{}:{}", proxy.username(), proxy.password().unwrap_or_default());
+        request.push_str(&format!("Proxy-Authorization: Basic {}\r\n", STANDARD.encode(credentials)));
+    }
+    request.push_str("\r\n");
+    stream.write_all(request.as_bytes())?;
+
+    // read byte by byte so nothing past the headers, i.e. the start of the handshake, is consumed
+    let mut response = Vec::new();
+    let mut byte = [0u8];
+    while !response.ends_with(b"\r\n\r\n") {
+        if response.len() >= MAX_PROXY_RESPONSE_BYTES {
+            return Err("proxy response to CONNECT is too long".into());
+        }
+        stream.read_exact(&mut byte)?;
+        response.push(byte[0]);
+    }
+    let response = String::from_utf8_lossy(&response);
+    let status_line = response.lines().next().unwrap_or_default();
+    if status_line.split_whitespace().nth(1) != Some("200") {
+        return Err(format!("proxy refused to connect to {host}:{port}: {status_line}").into());
+    }
+    Ok(stream)
+}
+
+// The proxy the environment configures for an https url, following the same variables as
+// the client: HTTPS_PROXY, then ALL_PROXY, unless NO_PROXY lists the host.
+fn system_proxy(url: &Url) -> Result<Option<Url>, Box<dyn std::error::Error>> {
+    let host = url.host_str().unwrap_or_default();
+    let no_proxy = env_var(&["NO_PROXY", "no_proxy"]).unwrap_or_default();
+    let bypassed = no_proxy
+        .split(',')
+        .map(str::trim)
+        .filter(|entry| !entry.is_empty())
+        .any(|entry| {
+            let domain = entry.trim_start_matches('.');
+            entry == "*" || host == domain || host.ends_with(&format!(".{domain}"))
+        });
+    if bypassed {
+        return Ok(None);
+    }
+    match env_var(&["HTTPS_PROXY", "https_proxy", "ALL_PROXY", "all_proxy"]) {
+        Some(proxy) if proxy.contains("://") => Ok(Some(Url::parse(&proxy)?)),
+        Some(proxy) => Ok(Some(Url::parse(&format!("http://{proxy}"))?)),
+        None => Ok(None),
+    }
+}
+
+fn env_var(names: &[&str]) -> Option<String> {
+    names.iter().find_map(|name| std::env::var(name).ok().filter(|value| !value.is_empty()))
+}
@@ -0,0 +1,95 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use elasticsearch::auth::{ClientCertificate, Credentials};
+use std::collections::HashMap;
+
+pub const USERNAME_VAR: &str = "ELASTIC_USERNAME";
+pub const PASSWORD_VAR: &str = "ELASTIC_PASSWORD";
+pub const API_KEY_VAR: &str = "ELASTIC_API_KEY";
+pub const BEARER_TOKEN_VAR: &str = "ELASTIC_BEARER_TOKEN";
+pub const CLOUD_ID_VAR: &str = "ELASTIC_CLOUD_ID";
+pub const CLIENT_CERT_PASSWORD_VAR: &str = "ELASTIC_CLIENT_CERT_PASSWORD";
+
+// The ways of authenticating the command line accepts; at most one may end up set.
+#[derive(Default)]
+pub struct AuthOptions {
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub api_key: Option<String>,
+    pub bearer_token: Option<String>,
+    pub client_cert: Option<String>,
+    pub client_cert_password: Option<String>,
+}
+
+impl AuthOptions {
+    // Fills in whatever wasn't given on the command line or in the environment.
+    pub fn fill_from(mut self, values: &HashMap<String, String>) -> AuthOptions {
+        let lookup = |name: &str| values.get(name).cloned();
+        self.username = self.username.or_else(|| lookup(USERNAME_VAR));
+        self.password = self.password.or_else(|| lookup(PASSWORD_VAR));
+        self.api_key = self.api_key.or_else(|| lookup(API_KEY_VAR));
+        self.bearer_token = self.bearer_token.or_else(|| lookup(BEARER_TOKEN_VAR));
+        self.client_cert_password = self.client_cert_password.or_else(|| lookup(CLIENT_CERT_PASSWORD_VAR));
+        self
+    }
+
+    pub fn credentials(self) -> Result<Option<Credentials>, Box<dyn std::error::Error>> {
+        let basic = self.username.is_some() || self.password.is_some();
+        let methods = [basic, self.api_key.is_some(), self.bearer_token.is_some(), self.client_cert.is_some()];
+        if methods.iter().filter(|&&method| method).count() > 1 {
+            return Err("use only one of username/password, api key, bearer token or client certificate".into());
+        }
+
+        // a password without a certificate is ignored, as it may come from the environment
+        if let Some(client_cert) = self.client_cert {
+            let pkcs12 = std::fs::read(client_cert)?;
+            return Ok(Some(Credentials::Certificate(ClientCertificate::Pkcs12(pkcs12, self.client_cert_password))));
+        }
+        if let Some(api_key) = self.api_key {
+            let (id, key) = split_api_key(&api_key)?;
+            return Ok(Some(Credentials::ApiKey(id, key)));
+        }
+        if let Some(bearer_token) = self.bearer_token {
+            return Ok(Some(Credentials::Bearer(bearer_token)));
+        }
+        match (self.username, self.password) {
+            (Some(username), Some(password)) => Ok(Some(Credentials::Basic(username, password))),
+            (None, None) => Ok(None),
+            _ => Err("--username and --password must be given together".into()),
+        }
+    }
+}
+
+// Accepts the "id:api_key" pair or the base64 "encoded" value Kibana hands out.
+fn split_api_key(api_key: &str) -> Result<(String, String), Box<dyn std::error::Error>> {
+    let decoded = if api_key.contains(':') {
+        api_key.to_string()
+    } else {
+        String::from_utf8(STANDARD.decode(api_key.trim())?)?
+    };
+    match decoded.split_once(':') {
+        Some((id, key)) if !id.is_empty() && !key.is_empty() => Ok((id.to_string(), key.to_string())),
+        _ => Err("api key should be \"id:api_key\" or its base64 encoding".into()),
+    }
+}
+
+// KEY=VALUE lines, the same format as the docker-compose .env file, so that file can be
+// pointed at directly to pick up ELASTIC_PASSWORD.
+pub fn read_credentials_file(path: &str) -> Result<HashMap<String, String>, Box<dyn std::error::Error>> {
+    let contents = std::fs::read_to_string(path)?;
+    let mut values = HashMap::new();
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if let Some((name, value)) = line.split_once('=') {
+            let value = value.trim();
+            let value = value.strip_prefix('"')
+                .and_then(|value| value.strip_suffix('"'))
+                .unwrap_or(value);
+            values.insert(name.trim().to_string(), value.to_string());
+        }
+    }
+    Ok(values)
+}
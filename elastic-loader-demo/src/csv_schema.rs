@@ -0,0 +1,272 @@
+use serde::{Deserialize};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::BufReader;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FieldType {
+    Long,
+    Double,
+    Boolean,
+    Keyword,
+    Text,
+}
+
+impl FieldType {
+    // Narrowest type the value fits, checked from most to least specific. Codes with leading
+    // zeros, and integers too wide for a long, stay keywords so no digit is lost.
+    fn of(value: &str) -> FieldType {
+        if has_leading_zero(value) || is_wide_integer(value) {
+            FieldType::Keyword
+        } else if value.parse::<i64>().is_ok() {
+            FieldType::Long
+        } else if parse_double(value).is_some() {
+            FieldType::Double
+        } else if parse_bool(value).is_some() {
+            FieldType::Boolean
+        } else {
+            FieldType::Keyword
+        }
+    }
+
+    // Widens this type just enough to also hold `other`.
+    fn widen(self, other: FieldType) -> FieldType {
+        match (self, other) {
+            (a, b) if a == b => a,
+            (FieldType::Long, FieldType::Double) | (FieldType::Double, FieldType::Long) => FieldType::Double,
+            _ => FieldType::Keyword,
+        }
+    }
+
+    fn mapping(self) -> Value {
+        match self {
+            FieldType::Long => json!({ "type": "long" }),
+            FieldType::Double => json!({ "type": "double" }),
+            FieldType::Boolean => json!({ "type": "boolean" }),
+            FieldType::Keyword => json!({ "type": "keyword", "ignore_above": 256 }),
+            FieldType::Text => json!({
+                "type": "text",
+                "fields": {
+                    "keyword": { "type": "keyword", "ignore_above": 256 }
+                }
+            }),
+        }
+    }
+
+    // None for cells that don't parse as the column type; those are left out of the document.
+    fn convert(self, value: &str) -> Option<Value> {
+        match self {
+            FieldType::Long => value.parse::<i64>().ok().map(Value::from),
+            FieldType::Double => parse_double(value).map(Value::from),
+            FieldType::Boolean => parse_bool(value).map(Value::from),
+            FieldType::Keyword | FieldType::Text => Some(Value::from(value)),
+        }
+    }
+}
+
+// Column names and types used to turn arbitrary CSV rows into JSON documents.
+#[derive(Clone)]
+pub struct CsvSchema {
+    fields: Vec<(String, FieldType)>,
+}
+
+impl CsvSchema {
+    // Reads the header and up to `sample_size` rows, giving each column the narrowest type
+    // all of its non-empty sampled values fit.
+    pub fn infer(filename: &str, sample_size: usize) -> Result<CsvSchema, Box<dyn std::error::Error>> {
+        let mut rdr = csv::Reader::from_path(filename)?;
+        let names = field_names(rdr.headers()?)?;
+        let mut types: Vec<Option<FieldType>> = vec![None; names.len()];
+        for result in rdr.records().take(sample_size) {
+            let record = result?;
+            for (field_type, value) in types.iter_mut().zip(record.iter()) {
+                let value = value.trim();
+                if value.is_empty() {
+                    continue;
+                }
+                let value_type = FieldType::of(value);
+                *field_type = Some(field_type.map_or(value_type, |field_type| field_type.widen(value_type)));
+            }
+        }
+        Ok(CsvSchema {
+            fields: names.into_iter()
+                .zip(types)
+                .map(|(name, field_type)| (name, field_type.unwrap_or(FieldType::Keyword)))
+                .collect(),
+        })
+    }
+
+    // The schema file is a JSON object of field name to type; columns it doesn't list are
+    // loaded as keywords.
+    pub fn from_file(filename: &str, schema_path: &str) -> Result<CsvSchema, Box<dyn std::error::Error>> {
+        let types: HashMap<String, FieldType> =
+            serde_json::from_reader(BufReader::new(File::open(schema_path)?))?;
+        let mut rdr = csv::Reader::from_path(filename)?;
+        let names = field_names(rdr.headers()?)?;
+        Ok(CsvSchema {
+            fields: names.into_iter()
+                .map(|name| {
+                    let field_type = types.get(&name).copied().unwrap_or(FieldType::Keyword);
+                    (name, field_type)
+                })
+                .collect(),
+        })
+    }
+
+    pub fn field_names(&self) -> Vec<String> {
+        self.fields.iter().map(|(name, _)| name.clone()).collect()
+    }
+
+    pub fn mapping(&self) -> Value {
+        let properties = self.fields.iter()
+            .map(|(name, field_type)| (name.clone(), field_type.mapping()))
+            .collect::<Map<String, Value>>();
+        json!({ "properties": properties })
+    }
+
+    // Empty cells are left out of the document instead of being indexed as empty strings.
+    pub fn convert(&self, record: &csv::StringRecord) -> Value {
+        let document = self.fields.iter()
+            .zip(record.iter())
+            .filter_map(|((name, field_type), value)| {
+                let value = value.trim();
+                if value.is_empty() {
+                    return None;
+                }
+                field_type.convert(value).map(|value| (name.clone(), value))
+            })
+            .collect::<Map<String, Value>>();
+        Value::Object(document)
+    }
+}
+
+// Header names as field names: "Case Vehicle ID" becomes "case_vehicle_id". Headers that
+// leave no name, or the same name as another column, are rejected rather than loaded wrong.
+fn field_names(headers: &csv::StringRecord) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut names: Vec<String> = Vec::with_capacity(headers.len());
+    for (column, header) in headers.iter().enumerate() {
+        let name = header.trim()
+            .to_lowercase()
+            .split(|c: char| !c.is_alphanumeric())
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<&str>>()
+            .join("_");
+        if name.is_empty() {
+            return Err(format!("csv column {} header \"{header}\" has no letters or digits to name a field", column + 1).into());
+        }
+        if let Some(other) = names.iter().position(|other| *other == name) {
+            return Err(format!("csv headers \"{}\" and \"{header}\" both become field \"{name}\"", &headers[other]).into());
+        }
+        names.push(name);
+    }
+    Ok(names)
+}
+
+// "00123", but not "0" or "0.5".
+fn has_leading_zero(value: &str) -> bool {
+    let digits = value.strip_prefix(['-', '+']).unwrap_or(value);
+    digits.len() > 1 && digits.starts_with('0') && digits.as_bytes()[1].is_ascii_digit()
+}
+
+// Only digits, but more than a long holds; as a double it would lose the last ones.
+fn is_wide_integer(value: &str) -> bool {
+    let digits = value.strip_prefix(['-', '+']).unwrap_or(value);
+    !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) && value.parse::<i64>().is_err()
+}
+
+fn parse_bool(value: &str) -> Option<bool> {
+    if value.eq_ignore_ascii_case("true") {
+        Some(true)
+    } else if value.eq_ignore_ascii_case("false") {
+        Some(false)
+    } else {
+        None
+    }
+}
+
+// Plain decimal numbers only; f64 parsing would also accept "nan" and "inf".
+fn parse_double(value: &str) -> Option<f64> {
+    if value.bytes().any(|b| b.is_ascii_digit()) {
+        value.parse::<f64>().ok().filter(|value| value.is_finite())
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::temp_file::TempFile;
+
+    fn infer(csv: &TempFile, sample_size: usize) -> Vec<(String, FieldType)> {
+        CsvSchema::infer(csv.path(), sample_size).unwrap().fields
+    }
+
+    fn infer_error(csv: &TempFile) -> String {
+        match CsvSchema::infer(csv.path(), 100) {
+            Ok(_) => panic!("schema inferred"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    fn field(name: &str, field_type: FieldType) -> (String, FieldType) {
+        (name.to_string(), field_type)
+    }
+
+    #[test]
+    fn infer_gives_each_column_its_narrowest_type() {
+        let csv = TempFile::new("narrow.csv", "Count,Ratio,Flag,Make\n1,0.5,true,Ford\n-2,3,FALSE,Honda\n");
+        assert_eq!(infer(&csv, 100), vec![
+            field("count", FieldType::Long),
+            field("ratio", FieldType::Double),
+            field("flag", FieldType::Boolean),
+            field("make", FieldType::Keyword),
+        ]);
+    }
+
+    #[test]
+    fn infer_widens_columns_with_mixed_values() {
+        let csv = TempFile::new("widen.csv", "a,b,c,d\n1,1,true,nan\n2.5,x,1,inf\n3,2,false,1\n");
+        assert_eq!(infer(&csv, 100), vec![
+            // long and double widen to double, anything else to keyword
+            field("a", FieldType::Double),
+            field("b", FieldType::Keyword),
+            field("c", FieldType::Keyword),
+            // "nan" and "inf" aren't numbers
+            field("d", FieldType::Keyword),
+        ]);
+    }
+
+    #[test]
+    fn infer_ignores_empty_cells_and_rows_past_the_sample() {
+        let csv = TempFile::new("sample.csv", "Vehicle Year,Empty\n2001, \n,\n2003,\nnot a year,\n");
+        assert_eq!(infer(&csv, 3), vec![
+            field("vehicle_year", FieldType::Long),
+            // a column with no values at all
+            field("empty", FieldType::Keyword),
+        ]);
+        assert_eq!(infer(&csv, 4)[0], field("vehicle_year", FieldType::Keyword));
+    }
+
+    #[test]
+    fn infer_keeps_codes_and_wide_integers_as_keywords() {
+        let csv = TempFile::new("codes.csv", "zip,fips,small,ratio,big\n00123,06037,0,0.5,12345678901234567890\n10001,12086,-3,00.5,1\n");
+        assert_eq!(infer(&csv, 100), vec![
+            field("zip", FieldType::Keyword),
+            field("fips", FieldType::Keyword),
+            field("small", FieldType::Long),
+            field("ratio", FieldType::Keyword),
+            field("big", FieldType::Keyword),
+        ]);
+    }
+
+    #[test]
+    fn infer_rejects_headers_that_make_no_or_the_same_field_name() {
+        let csv = TempFile::new("unnamed.csv", "year,#\n2001,1\n");
+        assert!(infer_error(&csv).contains("column 2 header \"#\""));
+        let csv = TempFile::new("duplicate.csv", "Vehicle Year,vehicle_year\n2001,2001\n");
+        assert!(infer_error(&csv).contains("\"Vehicle Year\" and \"vehicle_year\" both become field \"vehicle_year\""));
+    }
+}
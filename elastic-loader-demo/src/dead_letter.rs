@@ -0,0 +1,87 @@
+use crate::load_error::LoadError;
+use serde::{Serialize};
+use serde_json::{Map, Value};
+use std::fs::{File, OpenOptions};
+use std::io::{BufWriter, Write};
+
+#[derive(Clone)]
+pub enum DeadLetterFormat {
+    Ndjson,
+    // Records of a fixed type, with its fields as columns.
+    Csv,
+    // JSON documents, such as rows converted by a CsvSchema, written in this column order.
+    CsvColumns(Vec<String>),
+}
+
+// Failed source records, written back out in a shape that can be fixed up and replayed.
+pub enum DeadLetterWriter {
+    Ndjson(BufWriter<File>),
+    Csv(Box<csv::Writer<File>>),
+    CsvColumns(Box<csv::Writer<File>>, Vec<String>),
+}
+
+impl DeadLetterWriter {
+    // With `append` the records of earlier runs are kept, e.g. when resuming a load; a csv
+    // header is only written to an empty file.
+    pub fn create(path: &str, format: &DeadLetterFormat, append: bool) -> Result<DeadLetterWriter, LoadError> {
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(append)
+            .truncate(!append)
+            .open(path)?;
+        Ok(match format {
+            DeadLetterFormat::Ndjson => DeadLetterWriter::Ndjson(BufWriter::new(file)),
+            DeadLetterFormat::Csv => {
+                let new_file = file.metadata()?.len() == 0;
+                DeadLetterWriter::Csv(Box::new(csv::WriterBuilder::new()
+                    .has_headers(new_file)
+                    .from_writer(file)))
+            }
+            DeadLetterFormat::CsvColumns(columns) => {
+                let new_file = file.metadata()?.len() == 0;
+                let mut writer = csv::Writer::from_writer(file);
+                if new_file {
+                    writer.write_record(columns)?;
+                }
+                DeadLetterWriter::CsvColumns(Box::new(writer), columns.clone())
+            }
+        })
+    }
+
+    pub fn write<T: Serialize>(&mut self, item: &T) -> Result<(), LoadError> {
+        match self {
+            DeadLetterWriter::Ndjson(writer) => {
+                serde_json::to_writer(&mut *writer, item)?;
+                writer.write_all(b"\n")?;
+            }
+            DeadLetterWriter::Csv(writer) => writer.serialize(item)?,
+            // the csv crate can't serialize maps, so documents are written cell by cell
+            DeadLetterWriter::CsvColumns(writer, columns) => {
+                let document = match serde_json::to_value(item)? {
+                    Value::Object(document) => document,
+                    _ => Map::new(),
+                };
+                writer.write_record(columns.iter().map(|column| cell(document.get(column))))?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<(), LoadError> {
+        match self {
+            DeadLetterWriter::Ndjson(writer) => writer.flush()?,
+            DeadLetterWriter::Csv(writer) | DeadLetterWriter::CsvColumns(writer, _) => writer.flush()?,
+        }
+        Ok(())
+    }
+}
+
+// Fields left out of a document, like empty cells, come back as empty cells.
+fn cell(value: Option<&Value>) -> String {
+    match value {
+        None | Some(Value::Null) => String::new(),
+        Some(Value::String(value)) => value.clone(),
+        Some(value) => value.to_string(),
+    }
+}
@@ -0,0 +1,154 @@
+use crate::load_error::LoadError;
+use serde::{Serialize};
+use serde_json::{Value};
+use sha2::{Digest, Sha256};
+use std::fmt::Write;
+
+// How the `_id` of each loaded document is chosen.
+#[derive(Clone)]
+pub enum IdStrategy {
+    // Position in the input, only stable as long as the input and its order are.
+    Sequence,
+    Field(String),
+    Template(Vec<TemplatePart>),
+    // SHA-256 of the serialized document, so identical rows always get the same id.
+    ContentHash,
+}
+
+#[derive(Clone)]
+pub enum TemplatePart {
+    Literal(String),
+    Field(String),
+}
+
+impl IdStrategy {
+    // Parses templates like "{year}-{case_vehicle_id}"; "{{" and "}}" are literal braces.
+    pub fn template(template: &str) -> Result<IdStrategy, Box<dyn std::error::Error>> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut field = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => field.push(c),
+                            None => return Err(format!("unclosed '{{' in id template \"{template}\"").into()),
+                        }
+                    }
+                    if field.is_empty() {
+                        return Err(format!("empty field name in id template \"{template}\"").into());
+                    }
+                    if !literal.is_empty() {
+                        parts.push(TemplatePart::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(TemplatePart::Field(field));
+                }
+                '}' => return Err(format!("unmatched '}}' in id template \"{template}\"").into()),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(TemplatePart::Literal(literal));
+        }
+        Ok(IdStrategy::Template(parts))
+    }
+
+    // `sequence` is the document's position, used only by IdStrategy::Sequence.
+    pub fn id<T: Serialize>(&self, sequence: usize, item: &T) -> Result<String, LoadError> {
+        match self {
+            IdStrategy::Sequence => Ok(sequence.to_string()),
+            IdStrategy::Field(field) => field_value(&serde_json::to_value(item)?, field),
+            IdStrategy::Template(parts) => {
+                let document = serde_json::to_value(item)?;
+                let mut id = String::new();
+                for part in parts {
+                    match part {
+                        TemplatePart::Literal(literal) => id.push_str(literal),
+                        TemplatePart::Field(field) => id.push_str(&field_value(&document, field)?),
+                    }
+                }
+                Ok(id)
+            }
+            IdStrategy::ContentHash => {
+                let digest = Sha256::digest(serde_json::to_vec(item)?);
+                let mut id = String::with_capacity(digest.len() * 2);
+                for byte in digest {
+                    // writing to a String can't fail
+                    let _ = write!(id, "{byte:02x}");
+                }
+                Ok(id)
+            }
+        }
+    }
+}
+
+fn field_value(document: &Value, field: &str) -> Result<String, LoadError> {
+    match document.get(field) {
+        Some(Value::String(value)) if !value.is_empty() => Ok(value.clone()),
+        Some(value @ (Value::Number(_) | Value::Bool(_))) => Ok(value.to_string()),
+        _ => Err(LoadError::DocumentId(format!("document has no value for id field \"{field}\""))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn template_id(template: &str, document: &Value) -> String {
+        match IdStrategy::template(template) {
+            Ok(strategy) => strategy.id(0, document).unwrap(),
+            Err(e) => panic!("template \"{template}\" rejected: {e}"),
+        }
+    }
+
+    fn template_error(template: &str) -> String {
+        match IdStrategy::template(template) {
+            Ok(_) => panic!("template \"{template}\" accepted"),
+            Err(e) => e.to_string(),
+        }
+    }
+
+    #[test]
+    fn template_fills_in_fields_between_literals() {
+        let document = json!({ "year": 2021, "case_vehicle_id": "17", "active": true });
+        assert_eq!(template_id("{year}-{case_vehicle_id}", &document), "2021-17");
+        assert_eq!(template_id("crash/{year}/{active}/", &document), "crash/2021/true/");
+        assert_eq!(template_id("fixed", &document), "fixed");
+    }
+
+    #[test]
+    fn template_doubled_braces_are_literal() {
+        let document = json!({ "year": 2021 });
+        assert_eq!(template_id("{{{year}}}", &document), "{2021}");
+        assert_eq!(template_id("{{year}}", &document), "{year}");
+        assert_eq!(template_id("a}}b{{c", &document), "a}b{c");
+    }
+
+    #[test]
+    fn template_rejects_unbalanced_or_empty_braces() {
+        assert!(template_error("{year").contains("unclosed '{'"));
+        assert!(template_error("{year}-{id").contains("unclosed '{'"));
+        assert!(template_error("year}").contains("unmatched '}'"));
+        assert!(template_error("{year}}").contains("unmatched '}'"));
+        assert!(template_error("{}-{year}").contains("empty field name"));
+    }
+
+    #[test]
+    fn template_fails_documents_missing_a_field() {
+        let strategy = IdStrategy::template("{year}-{case_vehicle_id}").unwrap_or_else(|e| panic!("{e}"));
+        assert!(matches!(strategy.id(0, &json!({ "year": 2021 })), Err(LoadError::DocumentId(_))));
+        assert!(matches!(strategy.id(0, &json!({ "year": 2021, "case_vehicle_id": "" })), Err(LoadError::DocumentId(_))));
+    }
+}
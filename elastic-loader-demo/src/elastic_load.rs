@@ -1,15 +1,138 @@
+use crate::latency::{Latencies, LatencySummary};
+use crate::load_error::LoadError;
 use serde::{Serialize};
+use serde_json::{Value};
+use std::collections::BTreeMap;
 use std::ops::AddAssign;
+use std::time::Duration;
+use tokio::sync::mpsc::Receiver;
 
 pub trait ElasticLoad {
-    // async fn reset_index(&self) ->  Result<(), Box<dyn std::error::Error>>;
-    async fn load<T: Serialize>(&self, items: &[Box<T>]) ->  Result<ElasticLoadResults, Box<dyn std::error::Error>>;
+    async fn reset_index(&self) ->  Result<(), LoadError>;
+    async fn load<T: Serialize>(&self, batches: Receiver<Vec<T>>) ->  Result<ElasticLoadResults, LoadError>;
+}
+
+// What each document is sent as; Update only touches existing documents while Upsert
+// creates them when missing.
+#[derive(Clone, Copy, PartialEq)]
+pub enum OpType {
+    Create,
+    Index,
+    Update,
+    Upsert,
+    Delete,
+}
+
+impl OpType {
+    // Key the bulk response reports each item under.
+    pub fn action(&self) -> &'static str {
+        match self {
+            OpType::Create => "create",
+            OpType::Index => "index",
+            OpType::Update | OpType::Upsert => "update",
+            OpType::Delete => "delete",
+        }
+    }
+
+    // As given to --op-type.
+    pub fn name(&self) -> &'static str {
+        match self {
+            OpType::Upsert => "upsert",
+            _ => self.action(),
+        }
+    }
+}
+
+// Body of an update request: the document as a partial update, optionally upserted.
+#[derive(Serialize)]
+pub struct UpdateBody<'a, T> {
+    pub doc: &'a T,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub doc_as_upsert: bool,
 }
 
 pub struct ElasticLoadResults {
     pub num_total: usize,
+    // documents the operation succeeded for, whatever the op type
     pub num_created: usize,
     pub num_failed: usize,
+    // rejected because of the document's current version, e.g. create on an existing id
+    pub num_conflicts: usize,
+    // documents re-sent after a transport error or a retryable rejection
+    pub num_retried: usize,
+    // rows a resumed load left out because an earlier run already loaded them
+    pub num_skipped: usize,
+    // documents in requests still unanswered when an interrupted load stopped waiting
+    pub num_abandoned: usize,
+    // stopped by a signal before the end of the input
+    pub interrupted: bool,
+    // summed `took` of the bulk responses, the time spent inside Elasticsearch
+    pub server_took: Duration,
+    pub failures: Vec<LoadFailure>,
+    pub node_stats: BTreeMap<String, NodeStats>,
+    // only set when the concurrency was adaptive
+    pub concurrency: Option<ConcurrencyStats>,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct NodeStats {
+    pub requests: usize,
+    pub documents: usize,
+    // request bodies, not counting HTTP headers
+    pub bytes: usize,
+    // summed request time, overlapping when requests run concurrently
+    pub elapsed: Duration,
+    pub latencies: Latencies,
+}
+
+// Where adaptive concurrency settled by the end of the load, and the most it allowed.
+#[derive(Debug, Clone, Copy)]
+pub struct ConcurrencyStats {
+    pub converged: usize,
+    pub peak: usize,
+}
+
+// One rejected document, as reported back by Elasticsearch.
+#[derive(Debug, Clone)]
+pub struct LoadFailure {
+    pub id: String,
+    pub status: Option<u16>,
+    pub error_type: String,
+    pub reason: String,
+}
+
+impl LoadFailure {
+    // Reads the `error` object of a bulk item or index response; a bare string is also accepted.
+    pub fn from_error(id: String, status: Option<u16>, error: &Value) -> LoadFailure {
+        let (error_type, reason) = match error {
+            Value::Object(error) => (
+                error.get("type").and_then(Value::as_str).unwrap_or("unknown").to_string(),
+                error.get("reason").and_then(Value::as_str).unwrap_or_default().to_string(),
+            ),
+            Value::String(reason) => (String::from("unknown"), reason.clone()),
+            _ => (String::from("unknown"), error.to_string()),
+        };
+        LoadFailure {
+            id,
+            status,
+            error_type,
+            reason,
+        }
+    }
+
+    // For every document of a request that failed as a whole.
+    pub fn from_load_error(id: String, error: &LoadError) -> LoadFailure {
+        LoadFailure {
+            id,
+            status: error.status(),
+            error_type: error.kind().to_string(),
+            reason: error.to_string(),
+        }
+    }
+
+    pub fn is_version_conflict(&self) -> bool {
+        self.status == Some(409) || self.error_type == "version_conflict_engine_exception"
+    }
 }
 
 impl ElasticLoadResults {
@@ -17,15 +140,64 @@ impl ElasticLoadResults {
         ElasticLoadResults {
             num_total: 0,
             num_created: 0,
-            num_failed: 0
+            num_failed: 0,
+            num_conflicts: 0,
+            num_retried: 0,
+            num_skipped: 0,
+            num_abandoned: 0,
+            interrupted: false,
+            server_took: Duration::ZERO,
+            failures: Vec::new(),
+            node_stats: BTreeMap::new(),
+            concurrency: None,
         }
     }
 }
 
+impl ElasticLoadResults {
+    // Requests that got a response, retries included.
+    pub fn num_requests(&self) -> usize {
+        self.node_stats.values().map(|stats| stats.requests).sum()
+    }
+
+    pub fn bytes_sent(&self) -> usize {
+        self.node_stats.values().map(|stats| stats.bytes).sum()
+    }
+
+    pub fn average_request_bytes(&self) -> usize {
+        self.bytes_sent() / self.num_requests().max(1)
+    }
+
+    // Latencies of the requests to all nodes together.
+    pub fn latency_summary(&self) -> Option<LatencySummary> {
+        let mut latencies = Latencies::default();
+        for stats in self.node_stats.values() {
+            latencies.extend(&stats.latencies);
+        }
+        latencies.summary()
+    }
+}
+
 impl AddAssign for ElasticLoadResults {
     fn add_assign(&mut self, other: Self) {
         self.num_total += other.num_total;
         self.num_created += other.num_created;
         self.num_failed += other.num_failed;
+        self.num_conflicts += other.num_conflicts;
+        self.num_retried += other.num_retried;
+        self.num_skipped += other.num_skipped;
+        self.num_abandoned += other.num_abandoned;
+        self.interrupted |= other.interrupted;
+        self.server_took += other.server_took;
+        self.failures.extend(other.failures);
+        for (node, stats) in other.node_stats {
+            let node_stats = self.node_stats.entry(node).or_default();
+            node_stats.requests += stats.requests;
+            node_stats.documents += stats.documents;
+            node_stats.bytes += stats.bytes;
+            node_stats.elapsed += stats.elapsed;
+            node_stats.latencies.extend(&stats.latencies);
+        }
+        self.concurrency = self.concurrency.or(other.concurrency);
     }
 }
\ No newline at end of file
@@ -0,0 +1,81 @@
+use serde_json::{json, Value};
+use std::fs::File;
+use std::io::BufReader;
+
+// Settings and mappings an index is created with when the loaders reset it.
+#[derive(Clone)]
+pub struct IndexDefinition {
+    settings: Value,
+    mappings: Option<Value>,
+}
+
+impl IndexDefinition {
+    pub fn new() -> IndexDefinition {
+        IndexDefinition {
+            settings: json!({
+                "index": {
+                    "number_of_shards": "3",
+                    "number_of_replicas": "1",
+                    "refresh_interval": "1s",
+                }
+            }),
+            mappings: None,
+        }
+    }
+
+    // Both files hold the bare object, i.e. what goes under "settings" or "mappings" in the
+    // create index request.
+    pub fn from_files(settings_path: Option<&str>, mappings_path: Option<&str>) -> Result<IndexDefinition, Box<dyn std::error::Error>> {
+        let mut definition = IndexDefinition::new();
+        if let Some(settings_path) = settings_path {
+            definition.settings = read_json(settings_path)?;
+        }
+        if let Some(mappings_path) = mappings_path {
+            definition.mappings = Some(read_json(mappings_path)?);
+        }
+        Ok(definition)
+    }
+
+    pub fn with_shards(self, shards: usize) -> IndexDefinition {
+        self.with_index_setting("number_of_shards", json!(shards.to_string()))
+    }
+
+    pub fn with_replicas(self, replicas: usize) -> IndexDefinition {
+        self.with_index_setting("number_of_replicas", json!(replicas.to_string()))
+    }
+
+    pub fn with_refresh_interval(self, refresh_interval: String) -> IndexDefinition {
+        self.with_index_setting("refresh_interval", json!(refresh_interval))
+    }
+
+    pub fn with_mappings(mut self, mappings: Value) -> IndexDefinition {
+        self.mappings = Some(mappings);
+        self
+    }
+
+    pub fn body(&self) -> Value {
+        let mut body = json!({ "settings": self.settings });
+        if let Some(mappings) = &self.mappings {
+            body["mappings"] = mappings.clone();
+        }
+        body
+    }
+
+    fn with_index_setting(mut self, name: &str, value: Value) -> IndexDefinition {
+        // settings may be given flat ("number_of_shards") or nested under "index"
+        if let Some(settings) = self.settings.as_object_mut() {
+            settings.remove(name);
+            settings.remove(&format!("index.{name}"));
+        }
+        if !self.settings["index"].is_object() {
+            self.settings["index"] = json!({});
+        }
+        self.settings["index"][name] = value;
+        self
+    }
+}
+
+fn read_json(path: &str) -> Result<Value, Box<dyn std::error::Error>> {
+    let reader = BufReader::new(File::open(path)?);
+    Ok(serde_json::from_reader(reader)?)
+}
@@ -0,0 +1,44 @@
+use std::time::Duration;
+
+// Every request's latency, kept as-is so percentiles are exact. Even a million single
+// requests only take a few megabytes.
+#[derive(Debug, Clone, Default)]
+pub struct Latencies {
+    samples: Vec<Duration>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct LatencySummary {
+    pub p50: Duration,
+    pub p90: Duration,
+    pub p99: Duration,
+    pub max: Duration,
+}
+
+impl Latencies {
+    pub fn record(&mut self, latency: Duration) {
+        self.samples.push(latency);
+    }
+
+    pub fn extend(&mut self, other: &Latencies) {
+        self.samples.extend_from_slice(&other.samples);
+    }
+
+    // None until something has been recorded.
+    pub fn summary(&self) -> Option<LatencySummary> {
+        let mut samples = self.samples.clone();
+        samples.sort_unstable();
+        Some(LatencySummary {
+            p50: percentile(&samples, 50.0)?,
+            p90: percentile(&samples, 90.0)?,
+            p99: percentile(&samples, 99.0)?,
+            max: *samples.last()?,
+        })
+    }
+}
+
+// Nearest-rank percentile of already sorted samples.
+fn percentile(sorted: &[Duration], percent: f64) -> Option<Duration> {
+    let rank = (percent / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted.get(rank.saturating_sub(1)).copied()
+}
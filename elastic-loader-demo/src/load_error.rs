@@ -0,0 +1,135 @@
+use elasticsearch::http::StatusCode;
+use elasticsearch::http::response::Response;
+use serde_json::Value;
+use std::fmt;
+
+// Why reading the input or loading it stopped, grouped by what the caller can do about it.
+// Documents Elasticsearch rejects one by one aren't errors, they're tallied as LoadFailures.
+pub enum LoadError {
+    // no response at all: connection refused, timed out, TLS handshake failed
+    Transport(elasticsearch::Error),
+    // 401 or 403, retrying won't help
+    Auth { status: u16, reason: String },
+    // any other unsuccessful status, with the error Elasticsearch gave
+    Status { status: u16, reason: String },
+    // a document that couldn't be turned into JSON, or a body that wasn't the JSON expected
+    Serialization(Box<dyn std::error::Error + Send + Sync>),
+    // reading a CSV input or writing the CSV dead-letter file
+    Csv(csv::Error),
+    // a bulk response whose items don't line up with the request
+    BulkItem(String),
+    // a document the id strategy can't make an id for
+    DocumentId(String),
+    // a checkpoint file that can't be resumed from
+    Checkpoint(String),
+    Io(std::io::Error),
+    // settings the loader can't be built from
+    Config(String),
+}
+
+impl LoadError {
+    // Short name of the category, reported as the error type of the documents it failed.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            LoadError::Transport(_) => "transport",
+            LoadError::Auth { .. } => "auth",
+            LoadError::Status { .. } => "status",
+            LoadError::Serialization(_) => "serialization",
+            LoadError::Csv(_) => "csv",
+            LoadError::BulkItem(_) => "bulk_item",
+            LoadError::DocumentId(_) => "document_id",
+            LoadError::Checkpoint(_) => "checkpoint",
+            LoadError::Io(_) => "io",
+            LoadError::Config(_) => "config",
+        }
+    }
+
+    pub fn status(&self) -> Option<u16> {
+        match self {
+            LoadError::Auth { status, .. } | LoadError::Status { status, .. } => Some(*status),
+            _ => None,
+        }
+    }
+
+    // Passes successful responses through, otherwise reads the error out of the body.
+    pub async fn check_response(response: Response) -> Result<Response, LoadError> {
+        let status = response.status_code();
+        if status.is_success() {
+            return Ok(response);
+        }
+        let body = response.json::<Value>().await.unwrap_or(Value::Null);
+        let reason = match &body["error"] {
+            Value::Object(error) => format!("{}: {}",
+                error.get("type").and_then(Value::as_str).unwrap_or("unknown"),
+                error.get("reason").and_then(Value::as_str).unwrap_or_default()),
+            Value::String(reason) => reason.clone(),
+            _ => status.canonical_reason().unwrap_or_default().to_string(),
+        };
+        let status_code = status.as_u16();
+        Err(if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+            LoadError::Auth { status: status_code, reason }
+        } else {
+            LoadError::Status { status: status_code, reason }
+        })
+    }
+}
+
+impl fmt::Display for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LoadError::Transport(e) => write!(f, "request failed: {e}"),
+            LoadError::Auth { status, reason } => write!(f, "cluster refused access with {status}: {reason}"),
+            LoadError::Status { status, reason } => write!(f, "request failed with {status}: {reason}"),
+            LoadError::Serialization(e) => write!(f, "invalid JSON: {e}"),
+            LoadError::Csv(e) => write!(f, "csv: {e}"),
+            LoadError::BulkItem(message) => write!(f, "unexpected bulk response: {message}"),
+            LoadError::DocumentId(message)
+            | LoadError::Checkpoint(message)
+            | LoadError::Config(message) => f.write_str(message),
+            LoadError::Io(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+// main returns errors as-is, so this is what ends up on the terminal.
+impl fmt::Debug for LoadError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl std::error::Error for LoadError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LoadError::Transport(e) => Some(e),
+            LoadError::Serialization(e) => Some(e.as_ref()),
+            LoadError::Csv(e) => Some(e),
+            LoadError::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<elasticsearch::Error> for LoadError {
+    fn from(e: elasticsearch::Error) -> LoadError {
+        LoadError::Transport(e)
+    }
+}
+
+impl From<serde_json::Error> for LoadError {
+    fn from(e: serde_json::Error) -> LoadError {
+        LoadError::Serialization(Box::new(e))
+    }
+}
+
+impl From<csv::Error> for LoadError {
+    fn from(e: csv::Error) -> LoadError {
+        LoadError::Csv(e)
+    }
+}
+
+impl From<std::io::Error> for LoadError {
+    fn from(e: std::io::Error) -> LoadError {
+        LoadError::Io(e)
+    }
+}
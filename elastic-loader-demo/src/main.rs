@@ -1,118 +1,723 @@
-#![feature(async_fn_in_trait)]
-
 extern crate core;
 
-use std::thread::sleep;
+use std::collections::HashMap;
+use std::sync::Arc;
 use std::time::{Duration, Instant};
-use elasticsearch::auth::Credentials;
+use elasticsearch::http::Url;
+use crate::bench::{print_bench_table, BenchResult};
 use crate::bulk_load::BulkElasticLoad;
-use crate::elastic_load::{ElasticLoad};
+use crate::dead_letter::DeadLetterFormat;
+use crate::index_definition::IndexDefinition;
+use crate::load_error::LoadError;
+use crate::connection::{check_cert_fingerprint, sniff_nodes, wait_for_cluster, ClusterAddress, ClusterStatus, ProxyConfig, TlsConfig, TlsValidation};
+use crate::credentials::{read_credentials_file, AuthOptions, API_KEY_VAR, BEARER_TOKEN_VAR, CLIENT_CERT_PASSWORD_VAR, CLOUD_ID_VAR, PASSWORD_VAR, USERNAME_VAR};
+use crate::csv_schema::CsvSchema;
+use crate::document_id::IdStrategy;
+use crate::elastic_load::{ElasticLoad, ElasticLoadResults, OpType};
 use crate::motor_vehicle_crash::MotorVehicleCrash;
+use crate::pipeline::put_pipeline;
+use crate::progress::{Progress, ProgressMode};
 use crate::single_load::SingleElasticLoad;
-use clap::Parser;
+use clap::{Args, Parser, Subcommand};
+use elasticsearch::auth::Credentials;
 use elasticsearch::params::Refresh;
+use serde::Serialize;
+use serde_json::Value;
+use tokio::sync::mpsc::Receiver;
+use crate::report::{write_report, OutputFormat, RunConfig, RunReport};
+use crate::record_stream::{stream_csv, stream_csv_documents, stream_json_array, stream_ndjson, ByteLimit};
+use crate::retry::RetryPolicy;
+use crate::shutdown::Shutdown;
+use crate::verify::{sample_documents, Verification, Verifier};
 
+mod bench;
 mod bulk_load;
+mod checkpoint;
+mod concurrency;
+mod connection;
+mod credentials;
+mod csv_schema;
+mod dead_letter;
+mod document_id;
 mod elastic_load;
+mod index_definition;
+mod latency;
+mod load_error;
 mod motor_vehicle_crash;
+mod pipeline;
+mod progress;
+mod record_stream;
+mod report;
+mod retry;
+mod shutdown;
 mod single_load;
+#[cfg(test)]
+mod temp_file;
+mod verify;
 
 enum Loader {
     BulkLoader(BulkElasticLoad),
     SingleLoader(SingleElasticLoad),
 }
 
+impl Loader {
+    // Size of the batches the reader should cut; single requests take one record at a time.
+    fn batch_size(&self) -> usize {
+        match self {
+            Loader::SingleLoader(_) => 1,
+            Loader::BulkLoader(loader) => loader.batch_size(),
+        }
+    }
+
+    fn byte_limit(&self) -> Option<ByteLimit> {
+        match self {
+            Loader::SingleLoader(_) => None,
+            Loader::BulkLoader(loader) => loader.byte_limit(),
+        }
+    }
+
+    fn document_id<T: Serialize>(&self, position: usize, item: &T) -> Result<String, LoadError> {
+        match self {
+            Loader::SingleLoader(loader) => loader.document_id(position, item),
+            Loader::BulkLoader(loader) => loader.document_id(position, item),
+        }
+    }
+
+    async fn reset_index(&self) -> Result<(), LoadError> {
+        match self {
+            Loader::SingleLoader(loader) => loader.reset_index().await,
+            Loader::BulkLoader(loader) => loader.reset_index().await,
+        }
+    }
+
+    async fn load<T: Serialize>(&self, batches: Receiver<Vec<T>>) -> Result<ElasticLoadResults, LoadError> {
+        match self {
+            Loader::SingleLoader(loader) => loader.load(batches).await,
+            Loader::BulkLoader(loader) => loader.load(batches).await,
+        }
+    }
+}
+
 // Run - Batch=10000, Async=1, Refresh=False
-// run --package elastic-loader-demo --bin elastic-loader-demo -- --csv-path "../../data/Motor_Vehicle_Crashes_-_Vehicle_Information__Three_Year_Window.csv" --cluster-url "https://127.0.0.1:9200/" --username "elastic" --password "elastic" --index-name "motor-vehicle-crashes" --batch-size 10000 --async-throttle 1 --refresh "false"
+// run --package elastic-loader-demo --bin elastic-loader-demo -- --csv-path "../../data/Motor_Vehicle_Crashes_-_Vehicle_Information__Three_Year_Window.csv" --cluster-url "https://127.0.0.1:9200/" --username "elastic" --credentials-file ".env" --index-name "motor-vehicle-crashes" --batch-size 10000 --async-throttle 1 --refresh "false"
 // Run Release - Batch=10000, Async=1, Refresh=False
-// run --release --package elastic-loader-demo --bin elastic-loader-demo -- --csv-path "../../data/Motor_Vehicle_Crashes_-_Vehicle_Information__Three_Year_Window.csv" --cluster-url "https://127.0.0.1:9200/" --username "elastic" --password "elastic" --index-name "motor-vehicle-crashes" --batch-size 10000 --async-throttle 1 --refresh "false"
+// run --release --package elastic-loader-demo --bin elastic-loader-demo -- --csv-path "../../data/Motor_Vehicle_Crashes_-_Vehicle_Information__Three_Year_Window.csv" --cluster-url "https://127.0.0.1:9200/" --username "elastic" --credentials-file ".env" --index-name "motor-vehicle-crashes" --batch-size 10000 --async-throttle 1 --refresh "false"
 
 #[derive(Parser)]
 struct Cli {
-    #[arg(long = "csv-path", value_hint = clap::ValueHint::FilePath)]
+    #[arg(long = "csv-path", visible_alias = "input-path", value_hint = clap::ValueHint::FilePath)]
     csv_path: String,
-    #[arg(long = "cluster-url", value_hint = clap::ValueHint::Url, default_value = "https://127.0.0.1:9200/")]
-    cluster_url: String,
-    #[arg(long = "username")]
-    username: String,
-    #[arg(long = "password")]
-    password: String,
+    #[arg(long = "input-format", default_value = "csv", value_parser =
+    clap::builder::PossibleValuesParser::new(["csv", "ndjson", "json"]))]
+    input_format: String,
+    #[arg(long = "cluster-url", value_hint = clap::ValueHint::Url, num_args = 1.., value_delimiter = ',',
+    default_value = "https://127.0.0.1:9200/")]
+    cluster_url: Vec<String>,
+    #[arg(long = "sniff", conflicts_with = "cloud_id")]
+    sniff: bool,
+    #[arg(long = "wait-for-cluster", default_value = "yellow", value_parser =
+    clap::builder::PossibleValuesParser::new(["green", "yellow", "red", "none"]))]
+    wait_for_cluster: String,
+    #[arg(long = "wait-for-cluster-timeout-secs", default_value_t = 60)]
+    wait_for_cluster_timeout_secs: u64,
+    #[arg(long = "cloud-id", env = CLOUD_ID_VAR, hide_env_values = true)]
+    cloud_id: Option<String>,
+    #[arg(long = "username", env = USERNAME_VAR)]
+    username: Option<String>,
+    #[arg(long = "password", env = PASSWORD_VAR, hide_env_values = true)]
+    password: Option<String>,
+    #[arg(long = "api-key", env = API_KEY_VAR, hide_env_values = true)]
+    api_key: Option<String>,
+    #[arg(long = "bearer-token", env = BEARER_TOKEN_VAR, hide_env_values = true)]
+    bearer_token: Option<String>,
+    #[arg(long = "credentials-file", value_hint = clap::ValueHint::FilePath)]
+    credentials_file: Option<String>,
+    #[arg(long = "client-cert", value_hint = clap::ValueHint::FilePath)]
+    client_cert: Option<String>,
+    #[arg(long = "client-cert-password", env = CLIENT_CERT_PASSWORD_VAR, hide_env_values = true)]
+    client_cert_password: Option<String>,
+    #[arg(long = "tls-validation", value_parser =
+    clap::builder::PossibleValuesParser::new(["none", "certificate", "full"]))]
+    tls_validation: Option<String>,
+    #[arg(long = "ca-cert", value_hint = clap::ValueHint::FilePath)]
+    ca_cert: Option<String>,
+    #[arg(long = "check-cert-fingerprint", conflicts_with_all = ["tls_validation", "ca_cert"])]
+    check_cert_fingerprint: Option<String>,
+    #[arg(long = "proxy", value_hint = clap::ValueHint::Url, conflicts_with = "system_proxy")]
+    proxy: Option<String>,
+    #[arg(long = "system-proxy")]
+    system_proxy: bool,
     #[arg(long = "index-name")]
     index_name: String,
     #[arg(long = "batch-size", default_value_t = 10_000)]
     batch_size: usize,
+    #[arg(long = "batch-bytes")]
+    batch_bytes: Option<usize>,
     #[arg(long = "async-throttle", default_value_t = 5)]
     async_throttle: usize,
+    #[arg(long = "adaptive-concurrency")]
+    adaptive_concurrency: bool,
+    #[arg(long = "max-concurrency", default_value_t = 32, requires = "adaptive_concurrency")]
+    max_concurrency: usize,
     #[arg(long = "refresh", default_value = "false", value_parser =
     clap::builder::PossibleValuesParser::new(["true", "false", "wait_for"]))]
     refresh: String,
+    #[arg(long = "dead-letter", value_hint = clap::ValueHint::FilePath)]
+    dead_letter: Option<String>,
+    #[arg(long = "dead-letter-format", default_value = "ndjson", value_parser =
+    clap::builder::PossibleValuesParser::new(["ndjson", "csv"]))]
+    dead_letter_format: String,
+    #[arg(long = "max-attempts", default_value_t = 1)]
+    max_attempts: usize,
+    #[arg(long = "retry-base-delay-ms", default_value_t = 100)]
+    retry_base_delay_ms: u64,
+    #[arg(long = "retry-max-delay-ms", default_value_t = 10_000)]
+    retry_max_delay_ms: u64,
+    #[arg(long = "retry-jitter", default_value_t = 0.5)]
+    retry_jitter: f64,
+    #[arg(long = "generic")]
+    generic: bool,
+    #[arg(long = "schema", value_hint = clap::ValueHint::FilePath, requires = "generic")]
+    schema: Option<String>,
+    #[arg(long = "schema-sample-size", default_value_t = 1_000)]
+    schema_sample_size: usize,
+    #[arg(long = "op-type", value_parser =
+    clap::builder::PossibleValuesParser::new(["create", "index", "update", "upsert", "delete"]))]
+    op_type: Option<String>,
+    #[arg(long = "id-field", conflicts_with_all = ["id_template", "id_hash"])]
+    id_field: Option<String>,
+    #[arg(long = "id-template", conflicts_with = "id_hash")]
+    id_template: Option<String>,
+    #[arg(long = "id-hash")]
+    id_hash: bool,
+    #[arg(long = "progress", default_value = "live", value_parser =
+    clap::builder::PossibleValuesParser::new(["live", "log", "quiet"]))]
+    progress: String,
+    #[arg(long = "progress-interval-secs", default_value_t = 10)]
+    progress_interval_secs: u64,
+    #[arg(long = "checkpoint", value_hint = clap::ValueHint::FilePath)]
+    checkpoint: Option<String>,
+    #[arg(long = "resume", requires = "checkpoint", conflicts_with = "recreate_index")]
+    resume: bool,
+    #[arg(long = "drain-timeout-secs", default_value_t = 30)]
+    drain_timeout_secs: u64,
+    #[arg(long = "abort-on-error")]
+    abort_on_error: bool,
+    #[arg(long = "pipeline")]
+    pipeline: Option<String>,
+    #[arg(long = "pipeline-file", value_hint = clap::ValueHint::FilePath, requires = "pipeline")]
+    pipeline_file: Option<String>,
+    #[arg(long = "verify")]
+    verify: bool,
+    #[arg(long = "verify-sample", default_value_t = 0, requires = "verify")]
+    verify_sample: usize,
+    #[arg(long = "output", default_value = "text", value_parser =
+    clap::builder::PossibleValuesParser::new(["text", "json", "csv"]))]
+    output: String,
+    #[arg(long = "output-file", value_hint = clap::ValueHint::FilePath)]
+    output_file: Option<String>,
+    #[arg(long = "recreate-index")]
+    recreate_index: bool,
+    #[arg(long = "index-settings", value_hint = clap::ValueHint::FilePath, requires = "recreate_index")]
+    index_settings: Option<String>,
+    #[arg(long = "mappings", value_hint = clap::ValueHint::FilePath, requires = "recreate_index")]
+    mappings: Option<String>,
+    #[arg(long = "shards", requires = "recreate_index")]
+    shards: Option<usize>,
+    #[arg(long = "replicas", requires = "recreate_index")]
+    replicas: Option<usize>,
+    #[arg(long = "refresh-interval", requires = "recreate_index")]
+    refresh_interval: Option<String>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    // Load the input once per combination of the values given, resetting the index before
+    // every run, and compare the throughput.
+    Bench(BenchArgs),
+}
+
+// Lists left out fall back to the single value given before the subcommand.
+#[derive(Args)]
+struct BenchArgs {
+    #[arg(long = "batch-sizes", value_delimiter = ',', num_args = 1..)]
+    batch_sizes: Vec<usize>,
+    #[arg(long = "throttles", value_delimiter = ',', num_args = 1..)]
+    throttles: Vec<usize>,
+    #[arg(long = "refreshes", value_delimiter = ',', num_args = 1.., value_parser =
+    clap::builder::PossibleValuesParser::new(["true", "false", "wait_for"]))]
+    refreshes: Vec<String>,
+    #[arg(long = "repeat", default_value_t = 3)]
+    repeat: usize,
+}
+
+// Batches the input is re-read in when sampling documents to verify.
+const VERIFY_BATCH_SIZE: usize = 1_000;
+
+// Everything loaders are built from that stays the same between runs.
+struct LoadSettings {
+    cluster_urls: Vec<String>,
+    cloud_id: Option<String>,
+    credentials: Option<Credentials>,
+    tls: TlsConfig,
+    index_name: String,
+    batch_bytes: Option<usize>,
+    // set when concurrency is adaptive
+    max_concurrency: Option<usize>,
+    retry_policy: RetryPolicy,
+    id_strategy: IdStrategy,
+    // None for each loader's own default: create in bulk, index for single requests
+    op_type: Option<OpType>,
+    index_definition: IndexDefinition,
+    dead_letter: Option<(String, DeadLetterFormat)>,
+    // path, and whether to resume from it
+    checkpoint: Option<(String, bool)>,
+    abort_on_error: bool,
+    pipeline: Option<String>,
+    input_path: String,
+    input_format: String,
+    schema: Option<CsvSchema>,
+    progress_mode: ProgressMode,
+    // documents spot-checked after loading, None to not verify at all
+    verify_sample: Option<usize>,
+    shutdown: Shutdown,
+    run_config: RunConfig,
+}
+
+impl LoadSettings {
+    fn op_type(&self, batch_size: usize) -> OpType {
+        match self.op_type {
+            Some(op_type) => op_type,
+            None if batch_size > 1 => OpType::Create,
+            None => OpType::Index,
+        }
+    }
+
+    fn loader(&self, batch_size: usize, throttle: usize, refresh: Refresh, progress: Arc<Progress>) -> Result<Loader, Box<dyn std::error::Error>> {
+        if batch_size > 1 {
+            let mut builder = BulkElasticLoad::builder()
+                .with_uris(self.cluster_urls.clone())
+                .with_tls(self.tls.clone())
+                .with_progress(progress)
+                .with_shutdown(self.shutdown.clone())
+                .with_index(self.index_name.clone())
+                .with_throttle(throttle)
+                .with_refresh(refresh)
+                .with_batch_size(batch_size)
+                .with_abort_on_error(self.abort_on_error)
+                .with_retry_policy(self.retry_policy)
+                .with_id_strategy(self.id_strategy.clone())
+                .with_op_type(self.op_type(batch_size))
+                .with_index_definition(self.index_definition.clone());
+            if let Some(credentials) = &self.credentials {
+                builder = builder.with_credentials(credentials.clone());
+            }
+            if let Some(cloud_id) = &self.cloud_id {
+                builder = builder.with_cloud_id(cloud_id.clone());
+            }
+            if let Some((dead_letter, dead_letter_format)) = &self.dead_letter {
+                builder = builder.with_dead_letter(dead_letter.clone(), dead_letter_format.clone());
+            }
+            if let Some(pipeline) = &self.pipeline {
+                builder = builder.with_pipeline(pipeline.clone());
+            }
+            if let Some((checkpoint, resume)) = &self.checkpoint {
+                builder = builder.with_checkpoint(checkpoint.clone(), *resume);
+            }
+            if let Some(batch_bytes) = self.batch_bytes {
+                builder = builder.with_batch_bytes(batch_bytes);
+            }
+            if let Some(max_concurrency) = self.max_concurrency {
+                builder = builder.with_adaptive_concurrency(max_concurrency);
+            }
+            Ok(Loader::BulkLoader(builder.build()?))
+        } else {
+            let mut builder = SingleElasticLoad::builder()
+                .with_uris(self.cluster_urls.clone())
+                .with_tls(self.tls.clone())
+                .with_progress(progress)
+                .with_shutdown(self.shutdown.clone())
+                .with_index(self.index_name.clone())
+                .with_throttle(throttle)
+                .with_refresh(refresh)
+                .with_retry_policy(self.retry_policy)
+                .with_id_strategy(self.id_strategy.clone())
+                .with_op_type(self.op_type(batch_size))
+                .with_index_definition(self.index_definition.clone());
+            if let Some(credentials) = &self.credentials {
+                builder = builder.with_credentials(credentials.clone());
+            }
+            if let Some(cloud_id) = &self.cloud_id {
+                builder = builder.with_cloud_id(cloud_id.clone());
+            }
+            if let Some((dead_letter, dead_letter_format)) = &self.dead_letter {
+                builder = builder.with_dead_letter(dead_letter.clone(), dead_letter_format.clone());
+            }
+            if let Some(pipeline) = &self.pipeline {
+                builder = builder.with_pipeline(pipeline.clone());
+            }
+            Ok(Loader::SingleLoader(builder.build()?))
+        }
+    }
+
+    // Loads the whole input once with the given batch size, throttle and refresh.
+    async fn run(&self, batch_size: usize, throttle: usize, refresh: &str, reset_index: bool) -> Result<(RunReport, ElasticLoadResults), Box<dyn std::error::Error>> {
+        let input_size = std::fs::metadata(&self.input_path)?.len();
+        let progress = Arc::new(Progress::new(self.progress_mode, input_size));
+        let loader = self.loader(batch_size, throttle, parse_refresh(refresh), progress.clone())?;
+        if reset_index {
+            loader.reset_index().await?;
+        }
+
+        let start = Instant::now();
+        progress.start();
+        let input_path = self.input_path.clone();
+        // "../../data/Motor_Vehicle_Crashes_-_Vehicle_Information__Three_Year_Window.csv"
+        let (tally, reader) = match (self.input_format.as_str(), &self.schema) {
+            ("ndjson", _) => {
+                let (documents, reader) = stream_ndjson(input_path, loader.batch_size(), loader.byte_limit(), progress.clone());
+                (loader.load(documents).await?, reader)
+            }
+            ("json", _) => {
+                let (documents, reader) = stream_json_array(input_path, loader.batch_size(), loader.byte_limit(), progress.clone());
+                (loader.load(documents).await?, reader)
+            }
+            (_, Some(schema)) => {
+                let (documents, reader) = stream_csv_documents(input_path, loader.batch_size(), loader.byte_limit(), schema.clone(), progress.clone());
+                (loader.load(documents).await?, reader)
+            }
+            (_, None) => {
+                let (crashes, reader) = stream_csv::<MotorVehicleCrash>(input_path, loader.batch_size(), loader.byte_limit(), progress.clone());
+                (loader.load(crashes).await?, reader)
+            }
+        };
+        let source_rows = reader.await??;
+        progress.finish();
+        let duration = start.elapsed();
+
+        // an interrupted load is known not to match the input
+        let verification = match self.verify_sample {
+            Some(_) if tally.interrupted => None,
+            Some(sample_size) => Some(self.verify(&loader, self.op_type(batch_size), &tally, source_rows, sample_size).await?),
+            None => None,
+        };
+
+        let run_config = RunConfig {
+            batch_size,
+            throttle,
+            refresh: refresh.to_string(),
+            op_type: self.op_type(batch_size).name().to_string(),
+            ..self.run_config.clone()
+        };
+        Ok((RunReport::new(&run_config, &tally, duration, source_rows, verification), tally))
+    }
+
+    // Counts what ended up in the index and compares a sample of it with the input, read a
+    // second time so the load itself isn't slowed down.
+    async fn verify(&self, loader: &Loader, op_type: OpType, tally: &ElasticLoadResults, source_rows: usize, sample_size: usize) -> Result<Verification, Box<dyn std::error::Error>> {
+        let address = match &self.cloud_id {
+            Some(cloud_id) => ClusterAddress::CloudId(cloud_id.clone()),
+            None => ClusterAddress::Urls(self.cluster_urls.iter()
+                .map(|uri| Url::parse(uri))
+                .collect::<Result<Vec<Url>, _>>()?),
+        };
+        let verifier = Verifier::new(address, self.credentials.clone(), &self.tls, self.index_name.clone(), op_type)?;
+        let index_count = verifier.count().await?;
+
+        let progress = Arc::new(Progress::quiet());
+        let input_path = self.input_path.clone();
+        let document_id = |position, item: &Value| loader.document_id(position, item);
+        let samples = match (self.input_format.as_str(), &self.schema) {
+            _ if sample_size == 0 => Vec::new(),
+            ("ndjson", _) => sample_documents(stream_ndjson(input_path, VERIFY_BATCH_SIZE, None, progress).0, sample_size, document_id).await?,
+            ("json", _) => sample_documents(stream_json_array(input_path, VERIFY_BATCH_SIZE, None, progress).0, sample_size, document_id).await?,
+            (_, Some(schema)) => sample_documents(stream_csv_documents(input_path, VERIFY_BATCH_SIZE, None, schema.clone(), progress).0, sample_size, document_id).await?,
+            (_, None) => sample_documents(stream_csv::<MotorVehicleCrash>(input_path, VERIFY_BATCH_SIZE, None, progress).0, sample_size, |position, item| loader.document_id(position, item)).await?,
+        };
+        let mismatches = verifier.spot_check(&samples).await?;
+        for id in &mismatches {
+            eprintln!("Verify: document {id} does not match its source record");
+        }
+
+        // a resumed load only created part of what's in the index, the rest came from earlier runs
+        let resumed = self.checkpoint.as_ref().is_some_and(|(_, resume)| *resume);
+        let count_matches = !verifier.expects_count() || resumed || index_count == tally.num_created as u64;
+        // every source row was either sent, whatever came of it, or skipped by a resumed load
+        let accounted_rows = tally.num_created + tally.num_failed + tally.num_conflicts + tally.num_skipped;
+        let rows_match = accounted_rows == source_rows;
+        if !rows_match {
+            eprintln!("Verify: {source_rows} source rows but {} created, {} failed, {} conflicts and {} skipped",
+                tally.num_created, tally.num_failed, tally.num_conflicts, tally.num_skipped);
+        }
+        Ok(Verification {
+            index_count,
+            spot_checked: samples.len(),
+            spot_check_mismatches: mismatches.len(),
+            passed: count_matches && rows_match && mismatches.is_empty(),
+        })
+    }
+}
+
+fn parse_refresh(refresh: &str) -> Refresh {
+    match refresh {
+        "true" => Refresh::True,
+        "wait_for" => Refresh::WaitFor,
+        _ => Refresh::False,
+    }
 }
 
 #[tokio::main]
 pub async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let cli = Cli::parse();
-    // "../../data/Motor_Vehicle_Crashes_-_Vehicle_Information__Three_Year_Window.csv"
-    let crashes = MotorVehicleCrash::load_csv(cli.csv_path)?;
-
-    let refresh = if cli.refresh == "true" { Refresh::True }
-    else if cli.refresh == "wait_for" { Refresh::WaitFor }
-    else { Refresh::False };
-
-    let loader = if cli.batch_size > 1 {
-        Loader::BulkLoader(
-            BulkElasticLoad::builder()
-                .with_uri(cli.cluster_url)
-                .with_credentials(Credentials::Basic(
-                    cli.username, cli.password))
-                .with_index(cli.index_name)
-                .with_throttle(cli.async_throttle)
-                .with_refresh(refresh)
-                .with_batch_size(cli.batch_size)
-                .build()?
-        )
+
+    let retry_policy = RetryPolicy::new(
+        cli.max_attempts,
+        Duration::from_millis(cli.retry_base_delay_ms),
+        Duration::from_millis(cli.retry_max_delay_ms),
+        cli.retry_jitter);
+
+    let schema = if cli.generic && cli.input_format == "csv" {
+        Some(match &cli.schema {
+            Some(schema_path) => CsvSchema::from_file(&cli.csv_path, schema_path)?,
+            None => CsvSchema::infer(&cli.csv_path, cli.schema_sample_size)?,
+        })
     } else {
-        Loader::SingleLoader(
-            SingleElasticLoad::builder()
-                .with_uri(cli.cluster_url)
-                .with_credentials(Credentials::Basic(
-                    cli.username, cli.password))
-                .with_index(cli.index_name)
-                .with_throttle(cli.async_throttle)
-                .with_refresh(refresh)
-                .build()?
-        )
+        None
+    };
+
+    // JSON documents have no fixed set of columns to write a csv with
+    let dead_letter_format = match (cli.dead_letter_format.as_str(), &schema) {
+        ("csv", Some(schema)) => DeadLetterFormat::CsvColumns(schema.field_names()),
+        ("csv", None) if cli.input_format == "csv" => DeadLetterFormat::Csv,
+        ("csv", None) => return Err(format!("--dead-letter-format csv can't be used with {} input", cli.input_format).into()),
+        _ => DeadLetterFormat::Ndjson,
     };
 
-    let (start, tally) = match loader {
-        Loader::SingleLoader(loader) => {
-            sleep(Duration::new(60, 0));
-            let start = Instant::now();
-            (start, loader.load(&crashes[..]).await?)
+    // single requests (a batch size of 1) have no batches to checkpoint, size or adapt to
+    let single_requests = match &cli.command {
+        Some(Command::Bench(bench)) if !bench.batch_sizes.is_empty() => bench.batch_sizes.iter().any(|&batch_size| batch_size <= 1),
+        _ => cli.batch_size <= 1,
+    };
+    if single_requests {
+        let bulk_only = [
+            ("--checkpoint", cli.checkpoint.is_some()),
+            ("--abort-on-error", cli.abort_on_error),
+            ("--batch-bytes", cli.batch_bytes.is_some()),
+            ("--adaptive-concurrency", cli.adaptive_concurrency),
+        ];
+        if let Some((option, _)) = bulk_only.iter().find(|(_, given)| *given) {
+            return Err(format!("{option} only applies to bulk loads, not to a batch size of 1").into());
         }
-        Loader::BulkLoader(loader) => {
-            sleep(Duration::new(60, 0));
-            let start = Instant::now();
-            (start, loader.load(&crashes[..]).await?)
+    }
+
+    // command line and environment win over the credentials file
+    let credentials_file = match &cli.credentials_file {
+        Some(path) => read_credentials_file(path)?,
+        None => HashMap::new(),
+    };
+    let credentials = AuthOptions {
+        username: cli.username,
+        password: cli.password,
+        api_key: cli.api_key,
+        bearer_token: cli.bearer_token,
+        client_cert: cli.client_cert,
+        client_cert_password: cli.client_cert_password,
+    }.fill_from(&credentials_file).credentials()?;
+    let cloud_id = cli.cloud_id.or_else(|| credentials_file.get(CLOUD_ID_VAR).cloned());
+
+    // a CA certificate on its own means it should be checked against, and cloud
+    // deployments have publicly signed certificates
+    let tls_validation = match cli.tls_validation.as_deref() {
+        Some("certificate") => TlsValidation::Certificate,
+        Some("full") => TlsValidation::Full,
+        Some(_) => TlsValidation::None,
+        None if cli.ca_cert.is_some() || cloud_id.is_some() => TlsValidation::Full,
+        None => TlsValidation::None,
+    };
+    let mut tls = TlsConfig::new().with_validation(tls_validation);
+    if let Some(ca_cert) = cli.ca_cert {
+        tls = tls.with_ca_cert(ca_cert);
+    }
+    if let Some(proxy) = cli.proxy {
+        tls = tls.with_proxy(ProxyConfig::Url(Url::parse(&proxy)?));
+    } else if cli.system_proxy {
+        tls = tls.with_proxy(ProxyConfig::System);
+    }
+
+    let op_type = cli.op_type.as_deref().map(|op_type| match op_type {
+        "index" => OpType::Index,
+        "update" => OpType::Update,
+        "upsert" => OpType::Upsert,
+        "delete" => OpType::Delete,
+        _ => OpType::Create,
+    });
+
+    let id_strategy = if let Some(id_field) = cli.id_field {
+        IdStrategy::Field(id_field)
+    } else if let Some(id_template) = &cli.id_template {
+        IdStrategy::template(id_template)?
+    } else if cli.id_hash {
+        IdStrategy::ContentHash
+    } else {
+        IdStrategy::Sequence
+    };
+
+    let mut index_definition = IndexDefinition::from_files(
+        cli.index_settings.as_deref(), cli.mappings.as_deref())?;
+    // JSON input is indexed with dynamic mappings unless a mappings file is given
+    if cli.mappings.is_none() && cli.input_format == "csv" {
+        let mappings = match &schema {
+            Some(schema) => schema.mapping(),
+            None => MotorVehicleCrash::mapping(),
+        };
+        index_definition = index_definition.with_mappings(mappings);
+    }
+    if let Some(shards) = cli.shards {
+        index_definition = index_definition.with_shards(shards);
+    }
+    if let Some(replicas) = cli.replicas {
+        index_definition = index_definition.with_replicas(replicas);
+    }
+    if let Some(refresh_interval) = cli.refresh_interval {
+        index_definition = index_definition.with_refresh_interval(refresh_interval);
+    }
+
+    let progress_mode = match cli.progress.as_str() {
+        "log" => ProgressMode::Log(Duration::from_secs(cli.progress_interval_secs)),
+        "quiet" => ProgressMode::Quiet,
+        _ => ProgressMode::Live,
+    };
+
+    let seeds = cli.cluster_url.iter()
+        .map(|uri| Url::parse(uri))
+        .collect::<Result<Vec<Url>, _>>()?;
+    let wait_for_status = match cli.wait_for_cluster.as_str() {
+        "green" => Some(ClusterStatus::Green),
+        "yellow" => Some(ClusterStatus::Yellow),
+        "red" => Some(ClusterStatus::Red),
+        _ => None,
+    };
+    let address = match &cloud_id {
+        Some(cloud_id) => ClusterAddress::CloudId(cloud_id.clone()),
+        None => ClusterAddress::Urls(seeds.clone()),
+    };
+    let timeout = Duration::from_secs(cli.wait_for_cluster_timeout_secs);
+    if let Some(fingerprint) = &cli.check_cert_fingerprint {
+        check_cert_fingerprint(&address, &tls, fingerprint, timeout).await?;
+    }
+    if let Some(wait_for_status) = wait_for_status {
+        wait_for_cluster(address.clone(), credentials.clone(), &tls, wait_for_status, timeout).await?;
+    }
+
+    // update and delete requests don't run ingest pipelines
+    if let (Some(_), Some(op_type)) = (&cli.pipeline, op_type) {
+        if !matches!(op_type, OpType::Create | OpType::Index) {
+            return Err(format!("--pipeline can't be used with --op-type {}", op_type.name()).into());
         }
+    }
+    if let (Some(pipeline), Some(pipeline_file)) = (&cli.pipeline, &cli.pipeline_file) {
+        put_pipeline(address, credentials.clone(), &tls, pipeline, pipeline_file).await?;
+    }
+
+    let cluster_urls = if cli.sniff {
+        let nodes = sniff_nodes(&seeds, credentials.clone(), &tls).await?;
+        nodes.iter().map(|url| url.to_string()).collect()
+    } else {
+        cli.cluster_url
     };
 
-    let total_records = tally.num_total;
-    let total_created = tally.num_created;
-    let total_failed = tally.num_failed;
-
-    let duration = start.elapsed();
-    println!("Total Records: {total_records:?}");
-    println!("Total Created: {total_created:?}");
-    println!("Total Failed: {total_failed:?}");
-    println!("Duration: {duration:?}");
-    let mut duration_secs = usize::try_from(duration.as_secs())?;
-    if duration_secs == 0 { duration_secs = 1; }
-    let records_per_second = total_records / duration_secs;
-    println!("Records Per Second: {records_per_second:?}");
-    println!();
-    println!();
-    println!();
+    let output_format = match cli.output.as_str() {
+        "json" => OutputFormat::Json,
+        "csv" => OutputFormat::Csv,
+        _ => OutputFormat::Text,
+    };
+
+    let settings = LoadSettings {
+        run_config: RunConfig {
+            input_path: cli.csv_path.clone(),
+            input_format: cli.input_format.clone(),
+            index: cli.index_name.clone(),
+            batch_size: cli.batch_size,
+            batch_bytes: cli.batch_bytes,
+            throttle: cli.async_throttle,
+            adaptive_concurrency: cli.adaptive_concurrency,
+            refresh: cli.refresh.clone(),
+            // each run reports the op type its loader used
+            op_type: cli.op_type.clone().unwrap_or_default(),
+            pipeline: cli.pipeline.clone(),
+            nodes: if cloud_id.is_some() { 1 } else { cluster_urls.len() },
+        },
+        cluster_urls,
+        cloud_id,
+        credentials,
+        tls,
+        index_name: cli.index_name,
+        batch_bytes: cli.batch_bytes,
+        max_concurrency: Some(cli.max_concurrency).filter(|_| cli.adaptive_concurrency),
+        retry_policy,
+        id_strategy,
+        op_type,
+        index_definition,
+        dead_letter: cli.dead_letter.map(|dead_letter| (dead_letter, dead_letter_format)),
+        checkpoint: cli.checkpoint.map(|checkpoint| (checkpoint, cli.resume)),
+        abort_on_error: cli.abort_on_error,
+        pipeline: cli.pipeline,
+        input_path: cli.csv_path,
+        input_format: cli.input_format,
+        schema,
+        progress_mode,
+        verify_sample: Some(cli.verify_sample).filter(|_| cli.verify),
+        shutdown: Shutdown::listen(Duration::from_secs(cli.drain_timeout_secs)),
+    };
+
+    match cli.command {
+        Some(Command::Bench(_)) if settings.checkpoint.is_some() => {
+            return Err("--checkpoint can't be used with bench, every run starts over".into());
+        }
+        Some(Command::Bench(bench)) => {
+            let batch_sizes = if bench.batch_sizes.is_empty() { vec![cli.batch_size] } else { bench.batch_sizes };
+            let throttles = if bench.throttles.is_empty() { vec![cli.async_throttle] } else { bench.throttles };
+            let refreshes = if bench.refreshes.is_empty() { vec![cli.refresh] } else { bench.refreshes };
+
+            let mut results = Vec::new();
+            'bench: for &batch_size in &batch_sizes {
+                for &throttle in &throttles {
+                    for refresh in &refreshes {
+                        let mut result = BenchResult::new(batch_size, throttle, refresh.clone());
+                        for _ in 0..bench.repeat.max(1) {
+                            let (report, tally) = settings.run(batch_size, throttle, refresh, true).await?;
+                            // each run is recorded as it finishes, so an interrupted bench keeps them
+                            if cli.output_file.is_some() {
+                                write_report(output_format, cli.output_file.as_deref(), &report, &tally)?;
+                            }
+                            // a partial run would drag the mean down, so the table stops before it
+                            if report.interrupted {
+                                if result.runs() > 0 {
+                                    results.push(result);
+                                }
+                                break 'bench;
+                            }
+                            result.add_run(report.records_per_sec);
+                        }
+                        results.push(result);
+                    }
+                }
+            }
+            print_bench_table(&results);
+        }
+        None => {
+            let (report, tally) = settings.run(cli.batch_size, cli.async_throttle, &settings.run_config.refresh, cli.recreate_index).await?;
+            write_report(output_format, cli.output_file.as_deref(), &report, &tally)?;
+            if report.interrupted {
+                return Err("load interrupted before the end of the input".into());
+            }
+            if report.verified == Some(false) {
+                return Err("verification failed: the index does not match what was loaded".into());
+            }
+        }
+    }
 
     Ok(())
 }
-
@@ -1,8 +1,12 @@
 use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
 
+// Numeric columns are left empty (or hold text like "Unknown") for some rows, so they are
+// read as options and anything that doesn't parse is dropped rather than failing the load.
 #[derive(Debug, Deserialize, Serialize)]
 pub struct MotorVehicleCrash {
-    year: String,
+    #[serde(deserialize_with = "csv::invalid_option")]
+    year: Option<u16>,
     case_vehicle_id: String,
     vehicle_body_type: String,
     registration_class: String,
@@ -10,10 +14,13 @@ pub struct MotorVehicleCrash {
     type_or_axles_of_truck_or_bus: String,
     direction_of_travel: String,
     fuel_type: String,
-    vehicle_year: String,
+    #[serde(deserialize_with = "csv::invalid_option")]
+    vehicle_year: Option<u16>,
     state_of_registration: String,
-    number_of_occupants: String,
-    engine_cylinders: String,
+    #[serde(deserialize_with = "csv::invalid_option")]
+    number_of_occupants: Option<u32>,
+    #[serde(deserialize_with = "csv::invalid_option")]
+    engine_cylinders: Option<u8>,
     vehicle_make: String,
     contributing_factor_1: String,
     contributing_factor_1_description: String,
@@ -24,13 +31,37 @@ pub struct MotorVehicleCrash {
 }
 
 impl MotorVehicleCrash {
-    pub fn load_csv(filename: String) -> Result<Vec<Box<MotorVehicleCrash>>, Box<dyn std::error::Error>> {
-        let mut rdr = csv::Reader::from_path(filename).unwrap();
-        let mut records: Vec<Box<MotorVehicleCrash>> = Vec::with_capacity(1_000_000);
-        for result in rdr.deserialize() {
-            let record: MotorVehicleCrash = result?;
-            records.push(Box::new(record));
-        }
-        Ok(records)
+    // Explicit mapping for the fields above, so numbers aggregate and codes aren't analyzed.
+    pub fn mapping() -> Value {
+        let keyword = json!({ "type": "keyword" });
+        let text = json!({
+            "type": "text",
+            "fields": {
+                "keyword": { "type": "keyword", "ignore_above": 256 }
+            }
+        });
+        json!({
+            "properties": {
+                "year": { "type": "short" },
+                "case_vehicle_id": keyword,
+                "vehicle_body_type": keyword,
+                "registration_class": keyword,
+                "action_prior_to_accident": keyword,
+                "type_or_axles_of_truck_or_bus": keyword,
+                "direction_of_travel": keyword,
+                "fuel_type": keyword,
+                "vehicle_year": { "type": "short" },
+                "state_of_registration": keyword,
+                "number_of_occupants": { "type": "integer" },
+                "engine_cylinders": { "type": "byte" },
+                "vehicle_make": keyword,
+                "contributing_factor_1": keyword,
+                "contributing_factor_1_description": text,
+                "contributing_factor_2": keyword,
+                "contributing_factor_2_description": text,
+                "event_type": text,
+                "partial_vin": keyword,
+            }
+        })
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,23 @@
+use crate::connection::{build_client, ClusterAddress, TlsConfig};
+use crate::load_error::LoadError;
+use elasticsearch::auth::Credentials;
+use elasticsearch::ingest::IngestPutPipelineParts;
+use serde_json::Value;
+use std::fs::File;
+use std::io::BufReader;
+
+// Creates the ingest pipeline `name`, or replaces it, from a file holding the body of the
+// put pipeline request, i.e. its "description" and "processors".
+pub async fn put_pipeline(address: ClusterAddress, credentials: Option<Credentials>, tls: &TlsConfig, name: &str, path: &str) -> Result<(), LoadError> {
+    let body: Value = serde_json::from_reader(BufReader::new(File::open(path)?))?;
+    let client = build_client(address, credentials, tls)
+        .map_err(|e| LoadError::Config(e.to_string()))?;
+    let response = client
+        .ingest()
+        .put_pipeline(IngestPutPipelineParts::Id(name))
+        .body(body)
+        .send()
+        .await?;
+    LoadError::check_response(response).await?;
+    Ok(())
+}
@@ -0,0 +1,137 @@
+use serde_json::json;
+use std::io::Write;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+// Keeps single requests, which complete one document at a time, from redrawing constantly.
+const LIVE_REDRAW_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Clone, Copy)]
+pub enum ProgressMode {
+    // A status line on stderr, redrawn as batches complete.
+    Live,
+    // A JSON line on stderr every interval, for logs and scripts.
+    Log(Duration),
+    Quiet,
+}
+
+// Running totals while loading, shared by the reader (how far into the input it is) and
+// the loader (what has been sent). Everything is written to stderr so stdout only holds
+// the summary.
+pub struct Progress {
+    mode: ProgressMode,
+    input_size: u64,
+    input_read: AtomicU64,
+    state: Mutex<ProgressState>,
+}
+
+struct ProgressState {
+    start: Instant,
+    sent: usize,
+    created: usize,
+    failed: usize,
+    last_report: Instant,
+}
+
+impl Progress {
+    // `input_size` is the size of the input file in bytes, used for the ETA.
+    pub fn new(mode: ProgressMode, input_size: u64) -> Progress {
+        let start = Instant::now();
+        Progress {
+            mode,
+            input_size,
+            input_read: AtomicU64::new(0),
+            state: Mutex::new(ProgressState {
+                start,
+                sent: 0,
+                created: 0,
+                failed: 0,
+                last_report: start,
+            }),
+        }
+    }
+
+    pub fn quiet() -> Progress {
+        Progress::new(ProgressMode::Quiet, 0)
+    }
+
+    // Rates are measured from here rather than from when the loaders were set up.
+    pub fn start(&self) {
+        let mut state = self.state.lock().unwrap();
+        state.start = Instant::now();
+        state.last_report = state.start;
+    }
+
+    pub fn input_read(&self, bytes: usize) {
+        self.input_read.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    // Called by the loaders for every batch (or single document) that has come back.
+    pub fn batch_done(&self, sent: usize, created: usize, failed: usize) {
+        let mut state = self.state.lock().unwrap();
+        state.sent += sent;
+        state.created += created;
+        state.failed += failed;
+        let interval = match self.mode {
+            ProgressMode::Live => LIVE_REDRAW_INTERVAL,
+            ProgressMode::Log(interval) => interval,
+            ProgressMode::Quiet => return,
+        };
+        if state.last_report.elapsed() >= interval {
+            state.last_report = Instant::now();
+            self.report(&state);
+        }
+    }
+
+    // Reports the final totals and, for the live line, moves past it.
+    pub fn finish(&self) {
+        let state = self.state.lock().unwrap();
+        match self.mode {
+            ProgressMode::Live => {
+                self.report(&state);
+                eprintln!();
+            }
+            ProgressMode::Log(_) => self.report(&state),
+            ProgressMode::Quiet => {}
+        }
+    }
+
+    fn report(&self, state: &ProgressState) {
+        let elapsed = state.start.elapsed();
+        let docs_per_sec = state.sent as f64 / elapsed.as_secs_f64().max(f64::EPSILON);
+        let eta = self.eta(elapsed);
+        match self.mode {
+            ProgressMode::Live => {
+                let eta = eta.map_or(String::from("-"), |eta| format!("{}s", eta.as_secs()));
+                let mut stderr = std::io::stderr().lock();
+                let _ = write!(stderr, "\r{} sent, {} created, {} failed, {:.0} docs/sec, ETA {}    ",
+                    state.sent, state.created, state.failed, docs_per_sec, eta);
+                let _ = stderr.flush();
+            }
+            ProgressMode::Log(_) => {
+                let line = json!({
+                    "elapsed_secs": elapsed.as_secs_f64(),
+                    "sent": state.sent,
+                    "created": state.created,
+                    "failed": state.failed,
+                    "docs_per_sec": docs_per_sec,
+                    "eta_secs": eta.map(|eta| eta.as_secs_f64()),
+                });
+                eprintln!("{line}");
+            }
+            ProgressMode::Quiet => {}
+        }
+    }
+
+    // Extrapolated from how much of the input has been read so far. The reader runs a few
+    // batches ahead of the loader, so this errs on the short side.
+    fn eta(&self, elapsed: Duration) -> Option<Duration> {
+        let read = self.input_read.load(Ordering::Relaxed);
+        if self.input_size == 0 || read == 0 {
+            return None;
+        }
+        let fraction = (read as f64 / self.input_size as f64).min(1.0);
+        Some(elapsed.mul_f64((1.0 - fraction) / fraction))
+    }
+}
@@ -0,0 +1,195 @@
+use crate::csv_schema::CsvSchema;
+use crate::load_error::LoadError;
+use crate::progress::Progress;
+use serde::Serialize;
+use serde::de::{DeserializeOwned, Deserializer, IgnoredAny, SeqAccess, Visitor};
+use serde_json::Value;
+use std::fmt;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read};
+use std::sync::Arc;
+use tokio::sync::mpsc::{channel, Receiver, Sender};
+use tokio::task::{spawn_blocking, JoinHandle};
+
+// Number of batches the reader may get ahead of the loader before it blocks.
+const DEFAULT_CHANNEL_BATCHES: usize = 4;
+
+// Caps the size of the bulk request a batch turns into.
+#[derive(Clone, Copy)]
+pub struct ByteLimit {
+    pub max_bytes: usize,
+    // what the loader adds to the request for each document besides its body, ex the action line
+    pub per_document: usize,
+}
+
+// Batches are cut at `batch_size` records, or earlier once the bulk request they make would
+// grow past `byte_limit`.
+pub fn stream_csv<T>(filename: String, batch_size: usize, byte_limit: Option<ByteLimit>, progress: Arc<Progress>) -> (Receiver<Vec<T>>, JoinHandle<Result<usize, LoadError>>)
+where
+    T: DeserializeOwned + Serialize + Send + 'static,
+{
+    let (mut sender, receiver) = BatchSender::new(batch_size, byte_limit);
+    let reader = spawn_blocking(move || {
+        let mut rdr = csv::Reader::from_reader(ProgressReader::open(&filename, progress)?);
+        for result in rdr.deserialize() {
+            if !sender.push(result?) {
+                break;
+            }
+        }
+        Ok(sender.finish())
+    });
+    (receiver, reader)
+}
+
+// Rows become JSON documents shaped by the schema instead of a fixed record type.
+pub fn stream_csv_documents(filename: String, batch_size: usize, byte_limit: Option<ByteLimit>, schema: CsvSchema, progress: Arc<Progress>) -> (Receiver<Vec<Value>>, JoinHandle<Result<usize, LoadError>>) {
+    let (mut sender, receiver) = BatchSender::new(batch_size, byte_limit);
+    let reader = spawn_blocking(move || {
+        let mut rdr = csv::Reader::from_reader(ProgressReader::open(&filename, progress)?);
+        for result in rdr.records() {
+            if !sender.push(schema.convert(&result?)) {
+                break;
+            }
+        }
+        Ok(sender.finish())
+    });
+    (receiver, reader)
+}
+
+// One JSON document per line; blank lines are skipped.
+pub fn stream_ndjson(filename: String, batch_size: usize, byte_limit: Option<ByteLimit>, progress: Arc<Progress>) -> (Receiver<Vec<Value>>, JoinHandle<Result<usize, LoadError>>) {
+    let (mut sender, receiver) = BatchSender::new(batch_size, byte_limit);
+    let reader = spawn_blocking(move || {
+        let rdr = BufReader::new(ProgressReader::open(&filename, progress)?);
+        for (line_idx, line) in rdr.lines().enumerate() {
+            let line = line?;
+            if line.trim().is_empty() {
+                continue;
+            }
+            let document = serde_json::from_str::<Value>(&line)
+                .map_err(|e| LoadError::Serialization(format!("line {}: {e}", line_idx + 1).into()))?;
+            if !sender.push(document) {
+                break;
+            }
+        }
+        Ok(sender.finish())
+    });
+    (receiver, reader)
+}
+
+// A single top-level array of documents, read element by element rather than all at once.
+pub fn stream_json_array(filename: String, batch_size: usize, byte_limit: Option<ByteLimit>, progress: Arc<Progress>) -> (Receiver<Vec<Value>>, JoinHandle<Result<usize, LoadError>>) {
+    let (mut sender, receiver) = BatchSender::new(batch_size, byte_limit);
+    let reader = spawn_blocking(move || {
+        let rdr = BufReader::new(ProgressReader::open(&filename, progress)?);
+        let mut deserializer = serde_json::Deserializer::from_reader(rdr);
+        deserializer.deserialize_seq(ArrayVisitor { sender: &mut sender })?;
+        deserializer.end()?;
+        Ok(sender.finish())
+    });
+    (receiver, reader)
+}
+
+struct ArrayVisitor<'a> {
+    sender: &'a mut BatchSender<Value>,
+}
+
+impl<'de> Visitor<'de> for ArrayVisitor<'_> {
+    type Value = ();
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a top-level array of documents")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<(), A::Error> {
+        while let Some(document) = seq.next_element::<Value>()? {
+            if !self.sender.push(document) {
+                // the loader hung up, skip over the rest so the array still parses
+                while seq.next_element::<IgnoredAny>()?.is_some() {}
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+// Counts the bytes read from the input towards the progress ETA.
+struct ProgressReader {
+    file: File,
+    progress: Arc<Progress>,
+}
+
+impl ProgressReader {
+    fn open(filename: &str, progress: Arc<Progress>) -> std::io::Result<ProgressReader> {
+        Ok(ProgressReader {
+            file: File::open(filename)?,
+            progress,
+        })
+    }
+}
+
+impl Read for ProgressReader {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.file.read(buf)?;
+        self.progress.input_read(read);
+        Ok(read)
+    }
+}
+
+// Cuts records into batches on the reader thread and hands them to the loader.
+struct BatchSender<T> {
+    sender: Sender<Vec<T>>,
+    batch: Vec<T>,
+    batch_size: usize,
+    byte_limit: Option<ByteLimit>,
+    // bulk request size of the documents in `batch`, only tracked with a byte limit
+    current_bytes: usize,
+    num_read: usize,
+}
+
+impl<T: Serialize> BatchSender<T> {
+    fn new(batch_size: usize, byte_limit: Option<ByteLimit>) -> (BatchSender<T>, Receiver<Vec<T>>) {
+        let batch_size = batch_size.max(1);
+        let (sender, receiver) = channel::<Vec<T>>(DEFAULT_CHANNEL_BATCHES);
+        (BatchSender {
+            sender,
+            batch: Vec::with_capacity(batch_size),
+            batch_size,
+            byte_limit,
+            current_bytes: 0,
+            num_read: 0,
+        }, receiver)
+    }
+
+    // Returns false once the loader has hung up and there's no point reading further.
+    // A document bigger than the byte limit still goes out, in a batch of its own.
+    fn push(&mut self, item: T) -> bool {
+        if let Some(byte_limit) = self.byte_limit {
+            // the newline ending each document in the bulk body
+            let item_bytes = serde_json::to_vec(&item).map_or(0, |document| document.len() + 1) + byte_limit.per_document;
+            if !self.batch.is_empty() && self.current_bytes + item_bytes > byte_limit.max_bytes && !self.send_batch() {
+                return false;
+            }
+            self.current_bytes += item_bytes;
+        }
+        self.batch.push(item);
+        self.num_read += 1;
+        if self.batch.len() < self.batch_size {
+            return true;
+        }
+        self.send_batch()
+    }
+
+    fn send_batch(&mut self) -> bool {
+        self.current_bytes = 0;
+        let full_batch = std::mem::replace(&mut self.batch, Vec::with_capacity(self.batch_size));
+        self.sender.blocking_send(full_batch).is_ok()
+    }
+
+    fn finish(self) -> usize {
+        if !self.batch.is_empty() {
+            let _ = self.sender.blocking_send(self.batch);
+        }
+        self.num_read
+    }
+}
@@ -0,0 +1,205 @@
+use crate::elastic_load::ElasticLoadResults;
+use crate::verify::Verification;
+use serde::{Serialize};
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Individual failures printed in the text summary; the rest are only counted.
+const MAX_REPORTED_FAILURES: usize = 20;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum OutputFormat {
+    Text,
+    // One JSON object per run, so runs can be appended to the same file.
+    Json,
+    // One row per run, with a header only when starting a new file.
+    Csv,
+}
+
+// How a run was set up, recorded alongside its statistics so runs can be compared.
+#[derive(Clone)]
+pub struct RunConfig {
+    pub input_path: String,
+    pub input_format: String,
+    pub index: String,
+    pub batch_size: usize,
+    pub batch_bytes: Option<usize>,
+    pub throttle: usize,
+    pub adaptive_concurrency: bool,
+    pub refresh: String,
+    pub op_type: String,
+    pub pipeline: Option<String>,
+    pub nodes: usize,
+}
+
+// A single flat record per run, as csv can't write nested structures.
+#[derive(Serialize)]
+pub struct RunReport {
+    pub timestamp: u64,
+    pub input_path: String,
+    pub input_format: String,
+    pub index: String,
+    pub loader: String,
+    pub batch_size: usize,
+    pub batch_bytes: Option<usize>,
+    pub throttle: usize,
+    pub adaptive_concurrency: bool,
+    pub refresh: String,
+    pub op_type: String,
+    pub pipeline: Option<String>,
+    pub nodes: usize,
+    pub total: usize,
+    pub created: usize,
+    pub failed: usize,
+    pub conflicts: usize,
+    pub retried: usize,
+    pub skipped: usize,
+    pub interrupted: bool,
+    pub abandoned: usize,
+    pub duration_secs: f64,
+    pub records_per_sec: f64,
+    pub requests: usize,
+    pub bytes_sent: usize,
+    pub average_request_bytes: usize,
+    pub server_took_secs: f64,
+    pub latency_p50_ms: Option<f64>,
+    pub latency_p90_ms: Option<f64>,
+    pub latency_p99_ms: Option<f64>,
+    pub latency_max_ms: Option<f64>,
+    pub converged_concurrency: Option<usize>,
+    pub peak_concurrency: Option<usize>,
+    // records read from the input
+    pub source_rows: usize,
+    pub index_count: Option<u64>,
+    pub spot_checked: Option<usize>,
+    pub spot_check_mismatches: Option<usize>,
+    pub verified: Option<bool>,
+}
+
+impl RunReport {
+    pub fn new(config: &RunConfig, tally: &ElasticLoadResults, duration: Duration, source_rows: usize, verification: Option<Verification>) -> RunReport {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |since_epoch| since_epoch.as_secs());
+        let duration_secs = duration.as_secs_f64();
+        let latency = tally.latency_summary();
+        let millis = |latency: Duration| latency.as_secs_f64() * 1000.0;
+        RunReport {
+            timestamp,
+            input_path: config.input_path.clone(),
+            input_format: config.input_format.clone(),
+            index: config.index.clone(),
+            loader: String::from(if config.batch_size > 1 { "bulk" } else { "single" }),
+            batch_size: config.batch_size,
+            batch_bytes: config.batch_bytes,
+            throttle: config.throttle,
+            adaptive_concurrency: config.adaptive_concurrency,
+            refresh: config.refresh.clone(),
+            op_type: config.op_type.clone(),
+            pipeline: config.pipeline.clone(),
+            nodes: config.nodes,
+            total: tally.num_total,
+            created: tally.num_created,
+            failed: tally.num_failed,
+            conflicts: tally.num_conflicts,
+            retried: tally.num_retried,
+            skipped: tally.num_skipped,
+            interrupted: tally.interrupted,
+            abandoned: tally.num_abandoned,
+            duration_secs,
+            records_per_sec: tally.num_total as f64 / duration_secs.max(f64::EPSILON),
+            requests: tally.num_requests(),
+            bytes_sent: tally.bytes_sent(),
+            average_request_bytes: tally.average_request_bytes(),
+            server_took_secs: tally.server_took.as_secs_f64(),
+            latency_p50_ms: latency.map(|latency| millis(latency.p50)),
+            latency_p90_ms: latency.map(|latency| millis(latency.p90)),
+            latency_p99_ms: latency.map(|latency| millis(latency.p99)),
+            latency_max_ms: latency.map(|latency| millis(latency.max)),
+            converged_concurrency: tally.concurrency.map(|concurrency| concurrency.converged),
+            peak_concurrency: tally.concurrency.map(|concurrency| concurrency.peak),
+            source_rows,
+            index_count: verification.map(|verification| verification.index_count),
+            spot_checked: verification.map(|verification| verification.spot_checked),
+            spot_check_mismatches: verification.map(|verification| verification.spot_check_mismatches),
+            verified: verification.map(|verification| verification.passed),
+        }
+    }
+}
+
+// Writes to stdout, or appends to `output_file`.
+pub fn write_report(format: OutputFormat, output_file: Option<&str>, report: &RunReport, tally: &ElasticLoadResults) -> Result<(), Box<dyn std::error::Error>> {
+    let (mut out, new_output): (Box<dyn Write>, bool) = match output_file {
+        Some(path) => {
+            let file = OpenOptions::new().create(true).append(true).open(path)?;
+            let new_output = file.metadata()?.len() == 0;
+            (Box::new(file), new_output)
+        }
+        None => (Box::new(std::io::stdout().lock()), true),
+    };
+    match format {
+        OutputFormat::Text => write_text(&mut out, report, tally)?,
+        OutputFormat::Json => {
+            serde_json::to_writer(&mut out, report)?;
+            writeln!(out)?;
+        }
+        OutputFormat::Csv => {
+            let mut wtr = csv::WriterBuilder::new()
+                .has_headers(new_output)
+                .from_writer(&mut out);
+            wtr.serialize(report)?;
+            wtr.flush()?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn write_text(out: &mut dyn Write, report: &RunReport, tally: &ElasticLoadResults) -> Result<(), Box<dyn std::error::Error>> {
+    writeln!(out, "Source Rows: {}", report.source_rows)?;
+    writeln!(out, "Total Records: {}", report.total)?;
+    writeln!(out, "Total Created: {}", report.created)?;
+    writeln!(out, "Total Failed: {}", report.failed)?;
+    writeln!(out, "Total Conflicts: {}", report.conflicts)?;
+    writeln!(out, "Total Retried: {}", report.retried)?;
+    if report.skipped > 0 {
+        writeln!(out, "Total Skipped: {}", report.skipped)?;
+    }
+    if report.interrupted {
+        writeln!(out, "Interrupted: stopped before the end of the input, {} documents left unanswered", report.abandoned)?;
+    }
+    writeln!(out, "Duration: {:.3}s", report.duration_secs)?;
+    writeln!(out, "Records Per Second: {:.1}", report.records_per_sec)?;
+    writeln!(out, "Requests: {}", report.requests)?;
+    writeln!(out, "Bytes Sent: {}", report.bytes_sent)?;
+    writeln!(out, "Average Request Bytes: {}", report.average_request_bytes)?;
+    if let Some(latency) = tally.latency_summary() {
+        writeln!(out, "Request Latency: p50={:?} p90={:?} p99={:?} max={:?}", latency.p50, latency.p90, latency.p99, latency.max)?;
+    }
+    if !tally.server_took.is_zero() {
+        writeln!(out, "Server Took: {:?}", tally.server_took)?;
+    }
+    if let Some(concurrency) = tally.concurrency {
+        writeln!(out, "Concurrency: converged={} peak={}", concurrency.converged, concurrency.peak)?;
+    }
+    if let (Some(index_count), Some(verified)) = (report.index_count, report.verified) {
+        writeln!(out, "Index Count: {index_count}")?;
+        if let (Some(spot_checked), Some(mismatches)) = (report.spot_checked, report.spot_check_mismatches) {
+            writeln!(out, "Spot Checked: {spot_checked} ({mismatches} mismatched)")?;
+        }
+        writeln!(out, "Verified: {}", if verified { "passed" } else { "FAILED" })?;
+    }
+    for (node, node_stats) in &tally.node_stats {
+        let node_docs_per_second = node_stats.documents as f64 / report.duration_secs.max(f64::EPSILON);
+        writeln!(out, "Node {node}: requests={} documents={} docs/sec={node_docs_per_second:.1}", node_stats.requests, node_stats.documents)?;
+    }
+    for failure in tally.failures.iter().take(MAX_REPORTED_FAILURES) {
+        let status = failure.status.map_or(String::from("-"), |status| status.to_string());
+        writeln!(out, "Failed: id={} status={} type={} reason={}", failure.id, status, failure.error_type, failure.reason)?;
+    }
+    if tally.failures.len() > MAX_REPORTED_FAILURES {
+        writeln!(out, "... and {} more failures", tally.failures.len() - MAX_REPORTED_FAILURES)?;
+    }
+    Ok(())
+}
@@ -0,0 +1,48 @@
+use rand::Rng;
+use std::time::Duration;
+
+#[derive(Clone, Copy)]
+pub struct RetryPolicy {
+    max_attempts: usize,
+    base_delay: Duration,
+    max_delay: Duration,
+    jitter: f64,
+}
+
+impl RetryPolicy {
+    // `jitter` is the fraction of each delay (0.0 - 1.0) that is randomized away.
+    pub fn new(max_attempts: usize, base_delay: Duration, max_delay: Duration, jitter: f64) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: max_attempts.max(1),
+            base_delay,
+            max_delay: max_delay.max(base_delay),
+            jitter: jitter.clamp(0.0, 1.0),
+        }
+    }
+
+    pub fn none() -> RetryPolicy {
+        RetryPolicy::new(1, Duration::ZERO, Duration::ZERO, 0.0)
+    }
+
+    pub fn can_retry(&self, attempt: usize) -> bool {
+        attempt < self.max_attempts
+    }
+
+    // Backoff to wait after the given (1-based) attempt failed.
+    pub fn delay(&self, attempt: usize) -> Duration {
+        let exponent = u32::try_from(attempt.saturating_sub(1)).unwrap_or(u32::MAX).min(31);
+        let delay = self.base_delay
+            .saturating_mul(1 << exponent)
+            .min(self.max_delay);
+        if self.jitter > 0.0 {
+            delay.mul_f64(1.0 - self.jitter * rand::thread_rng().gen::<f64>())
+        } else {
+            delay
+        }
+    }
+
+    // Statuses worth re-sending: rejected executions and an overloaded or restarting node.
+    pub fn is_retryable_status(status: u16) -> bool {
+        matches!(status, 429 | 502 | 503 | 504)
+    }
+}
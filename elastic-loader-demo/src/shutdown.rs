@@ -0,0 +1,65 @@
+use std::time::Duration;
+use tokio::sync::watch;
+
+// Set once SIGINT or SIGTERM arrives. The loaders then stop taking new batches and give the
+// requests already sent up to `drain_timeout` to come back, so the results so far can still
+// be reported and checkpointed.
+#[derive(Clone)]
+pub struct Shutdown {
+    requested: watch::Receiver<bool>,
+    drain_timeout: Duration,
+}
+
+impl Shutdown {
+    // Installs the signal handlers; a second signal exits straight away.
+    pub fn listen(drain_timeout: Duration) -> Shutdown {
+        let (sender, requested) = watch::channel(false);
+        tokio::spawn(async move {
+            if wait_for_signal().await.is_err() {
+                return;
+            }
+            eprintln!("\nStopping: waiting up to {}s for requests already sent, interrupt again to quit now", drain_timeout.as_secs());
+            let _ = sender.send(true);
+            if wait_for_signal().await.is_ok() {
+                std::process::exit(130);
+            }
+        });
+        Shutdown {
+            requested,
+            drain_timeout,
+        }
+    }
+
+    // For loaders that aren't interrupted from outside.
+    pub fn never() -> Shutdown {
+        let (_, requested) = watch::channel(false);
+        Shutdown {
+            requested,
+            drain_timeout: Duration::ZERO,
+        }
+    }
+
+    pub fn drain_timeout(&self) -> Duration {
+        self.drain_timeout
+    }
+
+    // Completes once shutdown has been requested, and never when it can't be any more.
+    pub async fn requested(&mut self) {
+        if self.requested.wait_for(|requested| *requested).await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+async fn wait_for_signal() -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+        tokio::select! {
+            result = tokio::signal::ctrl_c() => result,
+            _ = terminate.recv() => Ok(()),
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await
+}
@@ -1,21 +1,41 @@
-use crate::elastic_load::{ElasticLoad, ElasticLoadResults};
-use serde_json::{json};
+use crate::connection::{build_client, ClusterAddress, NodeStatsRecorder, TlsConfig};
+use crate::dead_letter::{DeadLetterFormat, DeadLetterWriter};
+use crate::document_id::IdStrategy;
+use crate::elastic_load::{ElasticLoad, ElasticLoadResults, LoadFailure, OpType, UpdateBody};
+use crate::index_definition::IndexDefinition;
+use crate::load_error::LoadError;
+use crate::progress::Progress;
+use crate::retry::RetryPolicy;
+use crate::shutdown::Shutdown;
+use serde_json::{json, Value};
 use serde::{Serialize};
-use elasticsearch::{Elasticsearch, IndexParts};
-use elasticsearch::http::{Url};
-use elasticsearch::http::transport::SingleNodeConnectionPool;
-use elasticsearch::http::transport::TransportBuilder;
+use elasticsearch::{CreateParts, DeleteParts, Elasticsearch, IndexParts, UpdateParts};
+use elasticsearch::http::response::Response;
+use elasticsearch::indices::{IndicesCreateParts, IndicesDeleteParts};
+use elasticsearch::http::{StatusCode, Url};
 use elasticsearch::auth::Credentials;
-use elasticsearch::cert::CertificateValidation;
 use elasticsearch::params::Refresh;
-use tokio::sync::{Semaphore};
+use tokio::sync::mpsc::Receiver;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::time::{sleep, sleep_until};
 use futures::stream::{FuturesUnordered, StreamExt};
 
 pub struct SingleElasticLoad {
     client: Elasticsearch,
     index: String,
-    semaphore: Semaphore,
+    throttle: usize,
     refresh: Refresh,
+    dead_letter: Option<(String, DeadLetterFormat)>,
+    // ingest pipeline the documents go through
+    pipeline: Option<String>,
+    retry_policy: RetryPolicy,
+    index_definition: IndexDefinition,
+    id_strategy: IdStrategy,
+    op_type: OpType,
+    node_stats: NodeStatsRecorder,
+    progress: Arc<Progress>,
+    shutdown: Shutdown,
 }
 
 impl SingleElasticLoad {
@@ -23,84 +43,231 @@ impl SingleElasticLoad {
         SingleElasticLoadBuilder::new()
     }
 
-    // async fn delete_index(&self) -> Result<(), Box<dyn std::error::Error>> {
-    //     self.client
-    //         .indices()
-    //         .delete(IndicesDeleteParts::Index(&[self.index.as_str()]))
-    //         .send()
-    //         .await?;
-    //     Ok(())
-    // }
-    //
-    // async fn create_index(&self) -> Result<(), Box<dyn std::error::Error>> {
-    //     self.client
-    //         .indices()
-    //         .create(IndicesCreateParts::Index(&self.index))
-    //         .body(json!({
-    //             "settings": {
-    //                 "index": {
-    //                     "number_of_shards": "3",
-    //                     "number_of_replicas": "1",
-    //                     "refresh_interval": "1s",
-    //                 }
-    //             }
-    //         }))
-    //         .send()
-    //         .await?;
-    //     Ok(())
-    // }
-
-    async fn load_item<T: Serialize>(&self, idx: usize, item: &Box<T>) -> bool {
-        let _permit = self.semaphore.acquire().await.unwrap();
+    // Id of the record at the given (0-based) position of the input; sequence ids start at 0.
+    pub fn document_id<T: Serialize>(&self, position: usize, item: &T) -> Result<String, LoadError> {
+        self.id_strategy.id(position, item)
+    }
+
+    async fn delete_index(&self) -> Result<(), LoadError> {
         let response = self.client
-            .index(IndexParts::IndexId(&self.index, &idx.to_string()))
-            .body(json!(item))
-            .refresh(self.refresh)
+            .indices()
+            .delete(IndicesDeleteParts::Index(&[self.index.as_str()]))
             .send()
-            .await;
-        match response {
-            Ok(response) => response.status_code().is_success(),
-            Err(_) => false,
+            .await?;
+        // nothing to delete on the first run
+        if response.status_code() != StatusCode::NOT_FOUND {
+            LoadError::check_response(response).await?;
+        }
+        Ok(())
+    }
+
+    async fn create_index(&self) -> Result<(), LoadError> {
+        let response = self.client
+            .indices()
+            .create(IndicesCreateParts::Index(&self.index))
+            .body(self.index_definition.body())
+            .send()
+            .await?;
+        LoadError::check_response(response).await?;
+        Ok(())
+    }
+
+    // Returns how many times the request was retried; on failure the item is handed back
+    // so it can be written to the dead-letter file.
+    async fn load_item<T: Serialize>(&self, id: String, item: T) -> (usize, Result<(), (LoadFailure, T)>) {
+        let body = json!(item);
+        let body_bytes = body.to_string().len();
+        let mut attempt = 1;
+        loop {
+            let request_start = Instant::now();
+            let response = self.send_item(&id, &body).await;
+            if let Ok(response) = &response {
+                self.node_stats.record(response.url(), 1, body_bytes, request_start.elapsed());
+            }
+            let retryable = match &response {
+                Ok(response) => RetryPolicy::is_retryable_status(response.status_code().as_u16()),
+                Err(_) => true,
+            };
+            if retryable && self.retry_policy.can_retry(attempt) {
+                sleep(self.retry_policy.delay(attempt)).await;
+                attempt += 1;
+                continue;
+            }
+            let outcome = match response {
+                Ok(response) if response.status_code().is_success() => Ok(()),
+                // matches bulk, where deleting a missing document isn't an error
+                Ok(response) if self.op_type == OpType::Delete
+                    && response.status_code() == StatusCode::NOT_FOUND => Ok(()),
+                Ok(response) => {
+                    let status = response.status_code().as_u16();
+                    let body = response.json::<Value>().await.unwrap_or(Value::Null);
+                    Err((LoadFailure::from_error(id, Some(status), &body["error"]), item))
+                }
+                Err(e) => Err((LoadFailure {
+                    id,
+                    status: None,
+                    error_type: String::from("transport"),
+                    reason: e.to_string(),
+                }, item)),
+            };
+            return (attempt - 1, outcome);
+        }
+    }
+
+    async fn send_item(&self, id: &str, body: &Value) -> Result<Response, elasticsearch::Error> {
+        match self.op_type {
+            OpType::Create => {
+                let mut request = self.client
+                    .create(CreateParts::IndexId(&self.index, id))
+                    .refresh(self.refresh);
+                if let Some(pipeline) = &self.pipeline {
+                    request = request.pipeline(pipeline);
+                }
+                request.body(body).send().await
+            }
+            OpType::Index => {
+                let mut request = self.client
+                    .index(IndexParts::IndexId(&self.index, id))
+                    .refresh(self.refresh);
+                if let Some(pipeline) = &self.pipeline {
+                    request = request.pipeline(pipeline);
+                }
+                request.body(body).send().await
+            }
+            OpType::Update | OpType::Upsert => self.client
+                .update(UpdateParts::IndexId(&self.index, id))
+                .body(UpdateBody { doc: body, doc_as_upsert: self.op_type == OpType::Upsert })
+                .refresh(self.refresh)
+                .send()
+                .await,
+            OpType::Delete => self.client
+                .delete(DeleteParts::IndexId(&self.index, id))
+                .refresh(self.refresh)
+                .send()
+                .await,
         }
     }
 }
 
 impl ElasticLoad for SingleElasticLoad {
-    // async fn reset_index(&self) -> Result<(), Box<dyn std::error::Error>> {
-    //     self.delete_index().await?;
-    //     self.create_index().await?;
-    //     Ok(())
-    // }
+    async fn reset_index(&self) -> Result<(), LoadError> {
+        self.delete_index().await?;
+        self.create_index().await?;
+        Ok(())
+    }
 
-    async fn load<T: Serialize>(&self, items: &[Box<T>]) -> Result<ElasticLoadResults, Box<dyn std::error::Error>> {
-        let mut responses = items.iter().enumerate()
-            .map(|(idx, item)| self.load_item(idx, item))
-            .collect::<FuturesUnordered<_>>();
+    async fn load<T: Serialize>(&self, mut batches: Receiver<Vec<T>>) -> Result<ElasticLoadResults, LoadError> {
+        let mut responses = FuturesUnordered::new();
+        let mut next_idx: usize = 0;
+        let mut exhausted = false;
+        let mut shutdown = self.shutdown.clone();
+        // set once shutdown is requested, after which only in-flight requests are waited for
+        let mut drain_deadline = None;
+        let mut abandoned: usize = 0;
 
         let mut successes: usize = 0;
-        let mut failures: usize = 0;
-        while let Some(response) = responses.next().await {
-            if response {
-                successes += 1;
-            } else {
-                failures += 1;
+        let mut conflicts: usize = 0;
+        let mut retried: usize = 0;
+        let mut failures = Vec::new();
+        let mut dead_letter = match &self.dead_letter {
+            Some((path, format)) => Some(DeadLetterWriter::create(path, format, false)?),
+            None => None,
+        };
+        // Only take the next item once fewer than `throttle` requests are in flight, which
+        // caps requests on the wire and keeps the channel from being drained faster than they
+        // complete.
+        while !exhausted || !responses.is_empty() {
+            tokio::select! {
+                Some((retries, response)) = responses.next(), if !responses.is_empty() => {
+                    retried += retries;
+                    match response {
+                        Ok(()) => {
+                            successes += 1;
+                            self.progress.batch_done(1, 1, 0);
+                        }
+                        Err((failure, _)) if LoadFailure::is_version_conflict(&failure) => {
+                            conflicts += 1;
+                            self.progress.batch_done(1, 0, 0);
+                        }
+                        Err((failure, item)) => {
+                            self.progress.batch_done(1, 0, 1);
+                            failures.push(failure);
+                            if let Some(dead_letter) = dead_letter.as_mut() {
+                                dead_letter.write(&item)?;
+                            }
+                        }
+                    }
+                }
+                batch = batches.recv(), if !exhausted && responses.len() < self.throttle => {
+                    match batch {
+                        Some(batch) => {
+                            for item in batch {
+                                let position = next_idx;
+                                next_idx += 1;
+                                // a document without an id fails on its own, the rest are still sent
+                                match self.document_id(position, &item) {
+                                    Ok(id) => responses.push(self.load_item(id, item)),
+                                    Err(error) => {
+                                        self.progress.batch_done(1, 0, 1);
+                                        failures.push(LoadFailure::from_load_error(String::new(), &error));
+                                        if let Some(dead_letter) = dead_letter.as_mut() {
+                                            dead_letter.write(&item)?;
+                                        }
+                                    }
+                                }
+                            }
+                        }
+                        None => exhausted = true,
+                    }
+                }
+                _ = shutdown.requested(), if !exhausted => {
+                    exhausted = true;
+                    drain_deadline = Some(tokio::time::Instant::now() + shutdown.drain_timeout());
+                }
+                _ = sleep_until(drain_deadline.unwrap_or_else(tokio::time::Instant::now)), if drain_deadline.is_some() => {
+                    abandoned = responses.len();
+                    break;
+                }
             }
         }
 
+        if let Some(dead_letter) = dead_letter.as_mut() {
+            dead_letter.flush()?;
+        }
+
         Ok(ElasticLoadResults {
-            num_failed: failures,
+            num_failed: failures.len(),
             num_created: successes,
-            num_total: failures + successes,
+            num_total: failures.len() + successes + conflicts,
+            num_conflicts: conflicts,
+            num_retried: retried,
+            num_skipped: 0,
+            num_abandoned: abandoned,
+            interrupted: drain_deadline.is_some(),
+            server_took: Duration::ZERO,
+            failures,
+            node_stats: self.node_stats.take(),
+            concurrency: None,
         })
     }
 }
 
 pub struct SingleElasticLoadBuilder {
-    uri: String,
+    uris: Vec<String>,
+    cloud_id: Option<String>,
     credentials: Option<Credentials>,
     index: Option<String>,
     throttle: usize,
     refresh: Refresh,
+    dead_letter: Option<(String, DeadLetterFormat)>,
+    pipeline: Option<String>,
+    retry_policy: RetryPolicy,
+    index_definition: IndexDefinition,
+    id_strategy: IdStrategy,
+    op_type: OpType,
+    tls: TlsConfig,
+    progress: Arc<Progress>,
+    shutdown: Shutdown,
 }
 
 const DEFAULT_SIMULTANEOUS_REQUESTS: usize = 1;
@@ -108,16 +275,33 @@ const DEFAULT_SIMULTANEOUS_REQUESTS: usize = 1;
 impl SingleElasticLoadBuilder {
     pub fn new() -> SingleElasticLoadBuilder {
         SingleElasticLoadBuilder {
-            uri: String::from("https://127.0.0.1:9200/"),
+            uris: vec![String::from("https://127.0.0.1:9200/")],
+            cloud_id: None,
             credentials: None,
             index: None,
             throttle: DEFAULT_SIMULTANEOUS_REQUESTS,
             refresh: Refresh::False,
+            dead_letter: None,
+            pipeline: None,
+            retry_policy: RetryPolicy::none(),
+            index_definition: IndexDefinition::new(),
+            id_strategy: IdStrategy::Sequence,
+            op_type: OpType::Index,
+            tls: TlsConfig::new(),
+            progress: Arc::new(Progress::quiet()),
+            shutdown: Shutdown::never(),
         }
     }
 
-    pub fn with_uri(mut self, uri: String) -> SingleElasticLoadBuilder {
-        self.uri = uri;
+    // Several nodes of the same cluster, used round-robin.
+    pub fn with_uris(mut self, uris: Vec<String>) -> SingleElasticLoadBuilder {
+        self.uris = uris;
+        self
+    }
+
+    // Takes precedence over the uris.
+    pub fn with_cloud_id(mut self, cloud_id: String) -> SingleElasticLoadBuilder {
+        self.cloud_id = Some(cloud_id);
         self
     }
 
@@ -141,25 +325,79 @@ impl SingleElasticLoadBuilder {
         self
     }
 
-    pub fn build(self) -> Result<SingleElasticLoad, Box<dyn std::error::Error>> {
-        let url = Url::parse(&self.uri)?;
-        let conn_pool = SingleNodeConnectionPool::new(url);
-        let mut transport_builder = TransportBuilder::new(conn_pool)
-            .disable_proxy()
-            .cert_validation(CertificateValidation::None);
-        if let Some(credentials) = self.credentials {
-            transport_builder = transport_builder.auth(credentials);
-        }
-        let transport = transport_builder.build()?;
-        let client = Elasticsearch::new(transport);
+    pub fn with_dead_letter(mut self, path: String, format: DeadLetterFormat) -> SingleElasticLoadBuilder {
+        self.dead_letter = Some((path, format));
+        self
+    }
+
+    // Only index and create requests go through the pipeline.
+    pub fn with_pipeline(mut self, pipeline: String) -> SingleElasticLoadBuilder {
+        self.pipeline = Some(pipeline);
+        self
+    }
+
+    pub fn with_retry_policy(mut self, retry_policy: RetryPolicy) -> SingleElasticLoadBuilder {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    pub fn with_index_definition(mut self, index_definition: IndexDefinition) -> SingleElasticLoadBuilder {
+        self.index_definition = index_definition;
+        self
+    }
+
+    pub fn with_id_strategy(mut self, id_strategy: IdStrategy) -> SingleElasticLoadBuilder {
+        self.id_strategy = id_strategy;
+        self
+    }
+
+    pub fn with_op_type(mut self, op_type: OpType) -> SingleElasticLoadBuilder {
+        self.op_type = op_type;
+        self
+    }
+
+    pub fn with_tls(mut self, tls: TlsConfig) -> SingleElasticLoadBuilder {
+        self.tls = tls;
+        self
+    }
+
+    pub fn with_progress(mut self, progress: Arc<Progress>) -> SingleElasticLoadBuilder {
+        self.progress = progress;
+        self
+    }
+
+    pub fn with_shutdown(mut self, shutdown: Shutdown) -> SingleElasticLoadBuilder {
+        self.shutdown = shutdown;
+        self
+    }
+
+    pub fn build(self) -> Result<SingleElasticLoad, LoadError> {
+        let address = match self.cloud_id {
+            Some(cloud_id) => ClusterAddress::CloudId(cloud_id),
+            None => ClusterAddress::Urls(self.uris.iter()
+                .map(|uri| Url::parse(uri))
+                .collect::<Result<Vec<Url>, _>>()
+                .map_err(|e| LoadError::Config(format!("invalid cluster url: {e}")))?),
+        };
+        let client = build_client(address, self.credentials, &self.tls)
+            .map_err(|e| LoadError::Config(e.to_string()))?;
         match self.index {
             Some(index) => Ok(SingleElasticLoad {
                 client,
                 index,
-                semaphore: Semaphore::new(self.throttle),
+                throttle: self.throttle.max(1),
                 refresh: self.refresh,
+                dead_letter: self.dead_letter,
+                pipeline: self.pipeline,
+                retry_policy: self.retry_policy,
+                index_definition: self.index_definition,
+                id_strategy: self.id_strategy,
+                op_type: self.op_type,
+                node_stats: NodeStatsRecorder::new(),
+                progress: self.progress,
+                shutdown: self.shutdown,
             }),
-            None => Err("Index name is required.".into())
+            None => Err(LoadError::Config(String::from("Index name is required.")))
         }
     }
 }
@@ -0,0 +1,23 @@
+use std::path::PathBuf;
+
+// A file in the temp directory for tests to read, removed again once the test is done with it.
+pub struct TempFile(PathBuf);
+
+impl TempFile {
+    // `name` only has to be unique among the tests, the process id keeps concurrent runs apart.
+    pub fn new(name: &str, contents: &str) -> TempFile {
+        let path = std::env::temp_dir().join(format!("elastic-loader-demo-{}-{name}", std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        TempFile(path)
+    }
+
+    pub fn path(&self) -> &str {
+        self.0.to_str().unwrap()
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.0);
+    }
+}
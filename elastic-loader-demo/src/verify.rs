@@ -0,0 +1,121 @@
+use crate::connection::{build_client, ClusterAddress, TlsConfig};
+use crate::elastic_load::OpType;
+use crate::load_error::LoadError;
+use elasticsearch::{CountParts, Elasticsearch, GetParts};
+use elasticsearch::auth::Credentials;
+use elasticsearch::http::StatusCode;
+use elasticsearch::indices::IndicesRefreshParts;
+use rand::Rng;
+use serde::{Serialize};
+use serde_json::{Value};
+use tokio::sync::mpsc::Receiver;
+
+// What was found in the index after loading.
+#[derive(Debug, Clone, Copy)]
+pub struct Verification {
+    pub index_count: u64,
+    pub spot_checked: usize,
+    pub spot_check_mismatches: usize,
+    pub passed: bool,
+}
+
+// Checks the index against what the loader reported and against the source records.
+pub struct Verifier {
+    client: Elasticsearch,
+    index: String,
+    op_type: OpType,
+}
+
+impl Verifier {
+    pub fn new(address: ClusterAddress, credentials: Option<Credentials>, tls: &TlsConfig, index: String, op_type: OpType) -> Result<Verifier, Box<dyn std::error::Error>> {
+        Ok(Verifier {
+            client: build_client(address, credentials, tls)?,
+            index,
+            op_type,
+        })
+    }
+
+    // Refreshes the index first, so documents loaded without refresh are counted too.
+    pub async fn count(&self) -> Result<u64, Box<dyn std::error::Error>> {
+        self.client
+            .indices()
+            .refresh(IndicesRefreshParts::Index(&[self.index.as_str()]))
+            .send()
+            .await?
+            .error_for_status_code()?;
+        let response = self.client
+            .count(CountParts::Index(&[self.index.as_str()]))
+            .send()
+            .await?
+            .error_for_status_code()?
+            .json::<Value>()
+            .await?;
+        response["count"].as_u64().ok_or_else(|| "count response has no count".into())
+    }
+
+    // Fetches each sampled document and compares the fields of the source record with it;
+    // deleted documents should be gone instead. Returns the ids that didn't match.
+    pub async fn spot_check(&self, samples: &[(String, Value)]) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+        let mut mismatches = Vec::new();
+        for (id, expected) in samples {
+            let response = self.client
+                .get(GetParts::IndexId(&self.index, id))
+                .send()
+                .await?;
+            let matches = if response.status_code() == StatusCode::NOT_FOUND {
+                self.op_type == OpType::Delete
+            } else {
+                let document = response.error_for_status_code()?.json::<Value>().await?;
+                self.op_type != OpType::Delete && contains_fields(&document["_source"], expected)
+            };
+            if !matches {
+                mismatches.push(id.clone());
+            }
+        }
+        Ok(mismatches)
+    }
+
+    // The count can only be checked when every successful document adds one to the index.
+    pub fn expects_count(&self) -> bool {
+        matches!(self.op_type, OpType::Create | OpType::Index | OpType::Upsert)
+    }
+}
+
+// Partial updates leave other fields in place, so only the fields of the record are compared.
+fn contains_fields(source: &Value, expected: &Value) -> bool {
+    match (source, expected) {
+        (Value::Object(source), Value::Object(expected)) => expected.iter()
+            .all(|(field, value)| source.get(field) == Some(value)),
+        _ => source == expected,
+    }
+}
+
+// Picks up to `size` records at random from the input (reservoir sampling), along with the
+// ids they were loaded under.
+pub async fn sample_documents<T, F>(mut batches: Receiver<Vec<T>>, size: usize, document_id: F) -> Result<Vec<(String, Value)>, Box<dyn std::error::Error>>
+where
+    T: Serialize,
+    F: Fn(usize, &T) -> Result<String, LoadError>,
+{
+    let mut samples = Vec::with_capacity(size);
+    let mut position = 0;
+    while let Some(batch) = batches.recv().await {
+        for item in batch {
+            let slot = if samples.len() < size {
+                Some(samples.len())
+            } else {
+                Some(rand::thread_rng().gen_range(0..=position)).filter(|&slot| slot < size)
+            };
+            if let Some(slot) = slot {
+                let sample = (document_id(position, &item)?, serde_json::to_value(&item)?);
+                if slot == samples.len() {
+                    samples.push(sample);
+                } else {
+                    samples[slot] = sample;
+                }
+            }
+            position += 1;
+        }
+    }
+    Ok(samples)
+}